@@ -9,20 +9,235 @@ pub struct TriggerRecordNoDiscord {
     pub trigger: String,
     pub case_sensitive: bool,
     pub regex: bool,
+    pub burst_count: i64,
+    pub burst_window: i64,
+    pub burst_distinct: bool,
+    pub urgent_minutes: i64,
+    pub escalate_to: Option<i64>,
+    pub disabled: bool,
 }
 
-pub struct TriggerRecordNoId {
+pub struct TriggerRecord {
+    pub id: i64,
     pub discord_user_id: i64,
     pub trigger: String,
     pub case_sensitive: bool,
     pub regex: bool,
+    pub burst_count: i64,
+    pub burst_window: i64,
+    pub burst_distinct: bool,
+    pub urgent_minutes: i64,
+    pub escalate_to: Option<i64>,
+}
+
+/// What to do with a DM once moderators delete the message it came from
+#[derive(sqlx::Type, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum OnDelete {
+    /// Leave the DM as is
+    Keep,
+    /// Replace the message with "[deleted by moderators]"
+    #[default]
+    Edit,
+    /// Delete the DM
+    Remove,
+}
+
+impl OnDelete {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnDelete::Keep => "keep",
+            OnDelete::Edit => "edit",
+            OnDelete::Remove => "remove",
+        }
+    }
+}
+
+/// How trigger matches reach a user
+#[derive(sqlx::Type, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum DeliveryMode {
+    /// A DM for every match
+    #[default]
+    Instant,
+    /// Periodic summaries only
+    Digest,
+    /// Both DMs and summaries
+    Both,
+}
+
+impl DeliveryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryMode::Instant => "instant",
+            DeliveryMode::Digest => "digest",
+            DeliveryMode::Both => "both",
+        }
+    }
+}
+
+/// What happens to notifications during quiet hours
+#[derive(sqlx::Type, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum QuietAction {
+    /// Keep them for a digest sent when quiet hours end
+    #[default]
+    Hold,
+    /// Throw them away
+    Drop,
+}
+
+impl QuietAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuietAction::Hold => "hold",
+            QuietAction::Drop => "drop",
+        }
+    }
+}
+
+/// Days a quiet hours window starts on
+#[derive(sqlx::Type, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum QuietDays {
+    #[default]
+    All,
+    Weekdays,
+    Weekends,
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl QuietDays {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuietDays::All => "all",
+            QuietDays::Weekdays => "weekdays",
+            QuietDays::Weekends => "weekends",
+            QuietDays::Mon => "mon",
+            QuietDays::Tue => "tue",
+            QuietDays::Wed => "wed",
+            QuietDays::Thu => "thu",
+            QuietDays::Fri => "fri",
+            QuietDays::Sat => "sat",
+            QuietDays::Sun => "sun",
+        }
+    }
+}
+
+/// Per-user settings, a missing row means all defaults
+#[derive(Debug, Default)]
+pub struct Settings {
+    pub gap_notices: bool,
+    pub on_delete: OnDelete,
+    /// How long to follow raids from monitored channels, 0 to not follow
+    pub raid_follow_minutes: i64,
+    /// How long to forward the next messages of an author after a trigger, 0 to not forward
+    pub followup_secs: i64,
+    /// Chat lines to attach before (and after, if `context_delay_secs` > 0) the triggering line
+    pub context_lines: i64,
+    /// How long to wait for the following lines, 0 for preceding lines only
+    pub context_delay_secs: i64,
+    /// Minimum time between two notifications, 0 for none
+    pub cooldown_secs: i64,
+    /// Minimum time between two notifications of the same trigger, 0 for none
+    pub trigger_cooldown_secs: i64,
+    /// Drop near-identical messages of the same author within this window, 0 to keep them
+    pub duplicate_secs: i64,
+    pub delivery_mode: DeliveryMode,
+    /// How long matches are collected for a digest
+    pub digest_minutes: i64,
+    /// Append matches to the last DM of a channel until it's quiet for this long, 0 for a DM each
+    pub coalesce_secs: i64,
+    /// IANA timezone name for quiet hours, `UTC` by default
+    pub timezone: String,
+    pub quiet_action: QuietAction,
+    /// Other users may name this one as the escalation of their urgent triggers
+    pub accept_escalations: bool,
+}
+
+pub async fn get_settings(con: &mut sqlx::SqliteConnection, discord_user_id: i64) -> Result<Settings, sqlx::Error> {
+    let settings = sqlx::query_as!(Settings,
+        r#"SELECT gap_notices, on_delete AS "on_delete: OnDelete", raid_follow_minutes, followup_secs, context_lines, context_delay_secs, cooldown_secs, trigger_cooldown_secs, duplicate_secs, delivery_mode AS "delivery_mode: DeliveryMode", digest_minutes, coalesce_secs, timezone, quiet_action AS "quiet_action: QuietAction", accept_escalations FROM settings WHERE discord_user_id = ?"#,
+        discord_user_id)
+        .fetch_optional(con)
+        .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// Ignore `username`, shared by `ignore add` and the notification buttons
+pub async fn add_ignore(con: &mut sqlx::SqliteConnection, discord_user_id: i64, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT OR IGNORE INTO ignores (discord_user_id, username) VALUES (?, ?)",
+        discord_user_id,
+        username)
+        .execute(con).await?;
+    Ok(())
+}
+
+/// Snooze a channel, a trigger or everything (both `None`) until `until`,
+/// a new snooze of the same thing replaces the old one
+pub async fn snooze(con: &mut sqlx::SqliteConnection, discord_user_id: i64, channel: Option<&str>, trigger_id: Option<i64>, until: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM snoozes WHERE discord_user_id = ? AND channel IS ? AND trigger_id IS ?",
+        discord_user_id,
+        channel,
+        trigger_id)
+        .execute(&mut *con).await?;
+    sqlx::query!("INSERT INTO snoozes (discord_user_id, channel, trigger_id, until) VALUES (?, ?, ?, ?)",
+        discord_user_id,
+        channel,
+        trigger_id,
+        until)
+        .execute(con).await?;
+    Ok(())
+}
+
+/// The database ID of the trigger with the user-facing `id`
+pub async fn trigger_id(con: &mut sqlx::SqliteConnection, discord_user_id: i64, id: i64) -> Result<Option<i64>, sqlx::Error> {
+    let mut triggers = sqlx::query!("SELECT id FROM triggers WHERE discord_user_id = ?",
+        discord_user_id)
+        .fetch_all(con)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect::<Vec<i64>>();
+    triggers.sort();
+    Ok(usize::try_from(id - 1).ok().and_then(|i| triggers.get(i)).copied())
+}
+
+/// Pause or resume a trigger (database ID), false if the user has no such trigger
+pub async fn set_trigger_disabled(con: &mut sqlx::SqliteConnection, discord_user_id: i64, trigger_id: i64, disabled: bool) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query!("UPDATE triggers SET disabled = ? WHERE discord_user_id = ? AND id = ?",
+        disabled,
+        discord_user_id,
+        trigger_id)
+        .execute(con).await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Add a column to a table created by an older version
+async fn ensure_column(pool: &Pool<Sqlite>, table: &str, column: &str, definition: &str) -> Result<(), sqlx::Error> {
+    let exists: bool = sqlx::query_scalar(&format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{}') WHERE name = ?", table))
+        .bind(column)
+        .fetch_one(pool)
+        .await?;
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
 }
 
 pub async fn setup() -> Result<Pool<Sqlite>, sqlx::Error> {
 
     let pool = SqlitePoolOptions::new()
         .min_connections(2)
-        .max_connections(3)
+        .max_connections(5)
         .connect(&env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
         .await
         .expect("Failed to connect to database");
@@ -61,6 +276,245 @@ pub async fn setup() -> Result<Pool<Sqlite>, sqlx::Error> {
                 )
             "#).execute(&pool).await?;
 
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS events
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    kind            TEXT NOT NULL,
+                    target          TEXT DEFAULT '' NOT NULL,
+                    UNIQUE(discord_user_id, kind, target) ON CONFLICT FAIL
+                )
+            "#).execute(&pool).await?;
+
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS settings
+                (
+                    discord_user_id INTEGER NOT NULL PRIMARY KEY,
+                    gap_notices     BOOLEAN DEFAULT FALSE NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    ensure_column(&pool, "settings", "on_delete", "TEXT DEFAULT 'edit' NOT NULL").await?;
+    ensure_column(&pool, "settings", "raid_follow_minutes", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "followup_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "context_lines", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "context_delay_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "cooldown_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "trigger_cooldown_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "duplicate_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "delivery_mode", "TEXT DEFAULT 'instant' NOT NULL").await?;
+    ensure_column(&pool, "settings", "digest_minutes", "INTEGER DEFAULT 60 NOT NULL").await?;
+    ensure_column(&pool, "settings", "coalesce_secs", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "settings", "timezone", "TEXT DEFAULT 'UTC' NOT NULL").await?;
+    ensure_column(&pool, "settings", "quiet_action", "TEXT DEFAULT 'hold' NOT NULL").await?;
+    ensure_column(&pool, "settings", "accept_escalations", "BOOLEAN DEFAULT FALSE NOT NULL").await?;
+    ensure_column(&pool, "triggers", "burst_count", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "triggers", "burst_window", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "triggers", "burst_distinct", "BOOLEAN DEFAULT FALSE NOT NULL").await?;
+    ensure_column(&pool, "triggers", "urgent_minutes", "INTEGER DEFAULT 0 NOT NULL").await?;
+    ensure_column(&pool, "triggers", "escalate_to", "INTEGER").await?;
+    ensure_column(&pool, "triggers", "disabled", "BOOLEAN DEFAULT FALSE NOT NULL").await?;
+
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS follows
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT NOT NULL,
+                    raided_from     TEXT NOT NULL,
+                    expires_at      INTEGER NOT NULL,
+                    UNIQUE(discord_user_id, channel) ON CONFLICT REPLACE
+                )
+            "#).execute(&pool).await?;
+
+    // EventSub raid subscriptions of the current session, `status`: active, refused
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS raid_subscriptions
+                (
+                    channel         TEXT NOT NULL PRIMARY KEY,
+                    status          TEXT NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    // Quiet hours windows, minutes of the day in the user's timezone, `end_minute` < `start_minute` goes past midnight
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS quiet_hours
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    days            TEXT DEFAULT 'all' NOT NULL,
+                    start_minute    INTEGER NOT NULL,
+                    end_minute      INTEGER NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    // Urgent matches re-pinging until acknowledged, `status`: open, acknowledged, expired
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS alerts
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT NOT NULL,
+                    author          TEXT NOT NULL,
+                    message         TEXT NOT NULL,
+                    repeat_minutes  INTEGER NOT NULL,
+                    escalate_to     INTEGER,
+                    status          TEXT DEFAULT 'open' NOT NULL,
+                    pings           INTEGER DEFAULT 0 NOT NULL,
+                    next_ping_at    INTEGER NOT NULL,
+                    acknowledged_by INTEGER,
+                    closed_at       INTEGER,
+                    created_at      INTEGER NOT NULL,
+                    trigger_ids     TEXT DEFAULT '[]' NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    ensure_column(&pool, "alerts", "trigger_ids", "TEXT DEFAULT '[]' NOT NULL").await?;
+
+    // Temporarily muted channels or triggers, both NULL mutes everything
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS snoozes
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT,
+                    trigger_id      INTEGER,
+                    until           INTEGER NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    // DM of each channel that new matches are appended to, until `updated_at` is too long ago
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS live_notifications
+                (
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT NOT NULL,
+                    dm_channel_id   INTEGER NOT NULL,
+                    dm_message_id   INTEGER NOT NULL,
+                    updated_at      INTEGER NOT NULL,
+                    PRIMARY KEY (discord_user_id, channel)
+                )
+            "#).execute(&pool).await?;
+
+    // Matches waiting for the next digest, `matches` is the number of highlights in the line,
+    // `held` ones came in during quiet hours
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS digest_items
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT NOT NULL,
+                    author          TEXT NOT NULL,
+                    message         TEXT NOT NULL,
+                    matches         INTEGER NOT NULL,
+                    held              BOOLEAN DEFAULT FALSE NOT NULL,
+                    created_at        INTEGER NOT NULL,
+                    twitch_message_id TEXT
+                )
+            "#).execute(&pool).await?;
+    ensure_column(&pool, "digest_items", "held", "BOOLEAN DEFAULT FALSE NOT NULL").await?;
+    ensure_column(&pool, "digest_items", "twitch_message_id", "TEXT").await?;
+
+    // Channels a user gets notified about: monitored ones and raids being followed
+    sqlx::query!(
+        r#"CREATE VIEW IF NOT EXISTS watched AS
+                SELECT discord_user_id, channel FROM channels
+                UNION
+                SELECT discord_user_id, channel FROM follows WHERE expires_at > CAST(strftime('%s', 'now') AS INTEGER)
+            "#).execute(&pool).await?;
+
+    // Notifications waiting for delivery, `status`: pending, dead
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS outbox
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    payload         TEXT NOT NULL,
+                    status          TEXT DEFAULT 'pending' NOT NULL,
+                    attempts        INTEGER DEFAULT 0 NOT NULL,
+                    next_attempt_at INTEGER NOT NULL,
+                    last_error      TEXT,
+                    created_at      INTEGER NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    // Events not queued because the user's backlog was full
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS outbox_overflow
+                (
+                    discord_user_id INTEGER NOT NULL PRIMARY KEY,
+                    dropped         INTEGER DEFAULT 0 NOT NULL
+                )
+            "#).execute(&pool).await?;
+
+    // DM failures per user, `undeliverable_since` is set once the bot stops matching for them
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS dm_status
+                (
+                    discord_user_id     INTEGER NOT NULL PRIMARY KEY,
+                    failures            INTEGER DEFAULT 0 NOT NULL,
+                    undeliverable_since INTEGER
+                )
+            "#).execute(&pool).await?;
+
+    // Guild channels getting the whole chat of a Twitch channel
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS mirrors
+                (
+                    id                 INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    channel            TEXT NOT NULL,
+                    discord_channel_id INTEGER NOT NULL,
+                    guild_id           INTEGER NOT NULL,
+                    created_by         INTEGER NOT NULL,
+                    UNIQUE(channel, discord_channel_id) ON CONFLICT FAIL
+                )
+            "#).execute(&pool).await?;
+
+    // Channels the bot has to be in
+    sqlx::query!(
+        r#"CREATE VIEW IF NOT EXISTS joined_channels AS
+                SELECT channel FROM watched
+                UNION
+                SELECT channel FROM mirrors
+            "#).execute(&pool).await?;
+
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS notifications
+                (
+                    id                INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id   INTEGER NOT NULL,
+                    dm_channel_id     INTEGER NOT NULL,
+                    dm_message_id     INTEGER NOT NULL,
+                    twitch_message_id TEXT NOT NULL,
+                    channel           TEXT NOT NULL,
+                    author            TEXT NOT NULL,
+                    created_at        INTEGER NOT NULL,
+                    line              TEXT
+                )
+            "#).execute(&pool).await?;
+    ensure_column(&pool, "notifications", "line", "TEXT").await?;
+
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS gaps
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    started_at      INTEGER NOT NULL,
+                    ended_at        INTEGER
+                )
+            "#).execute(&pool).await?;
+
+    sqlx::query!(
+        r#"CREATE TABLE IF NOT EXISTS presence
+                (
+                    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    discord_user_id INTEGER NOT NULL,
+                    channel         TEXT NOT NULL,
+                    username        TEXT NOT NULL,
+                    UNIQUE(discord_user_id, channel, username)
+                )
+            "#).execute(&pool).await?;
+
     tx.commit().await?;
 
     Ok(pool)
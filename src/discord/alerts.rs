@@ -0,0 +1,172 @@
+use std::time::Duration;
+use serenity::builder::CreateComponents;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::prelude::*;
+use tracing::{info, error};
+
+use crate::discord::outbox::{Outbox, OutboxError};
+use crate::discord::{quiet, TriggerEvent};
+use crate::twitch::{TwitchMessageSimple, Urgent};
+
+
+/// `custom_id` of the Acknowledge button is this followed by the alert ID
+pub const ACK_PREFIX: &str = "ack:";
+const CHECK_EVERY: Duration = Duration::from_secs(30);
+/// Re-pings before an alert is given up on
+const MAX_REPINGS: i64 = 5;
+/// The escalation user is pinged from this re-ping on
+const ESCALATE_AFTER: i64 = 2;
+/// Closed alerts are kept this long
+const CLOSED_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Alert that keeps re-pinging until acknowledged
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Alert {
+    pub id: i64,
+    pub channel: String,
+    pub author: String,
+    pub message: String,
+    pub pings: i64,
+}
+
+/// Store a new alert for an urgent match, returns its ID for the Acknowledge button
+pub async fn open(pool: &sqlx::SqlitePool, receiver: u64, message: &TwitchMessageSimple, urgent: &Urgent) -> Result<i64, sqlx::Error> {
+    let receiver = receiver as i64;
+    let now = chrono::Utc::now().timestamp();
+    let next_ping_at = now + urgent.minutes * 60;
+    let trigger_ids = serde_json::to_string(&message.trigger_ids).unwrap_or_else(|_| "[]".to_string());
+    let id = sqlx::query!("INSERT INTO alerts (discord_user_id, channel, author, message, repeat_minutes, escalate_to, next_ping_at, created_at, trigger_ids) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        receiver,
+        message.channel,
+        message.author,
+        message.message,
+        urgent.minutes,
+        urgent.escalate_to,
+        next_ping_at,
+        now,
+        trigger_ids)
+        .execute(pool).await?
+        .last_insert_rowid();
+    Ok(id)
+}
+
+/// The match behind alert `id`, enough to rebuild the buttons of its DM
+pub async fn message(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<TwitchMessageSimple>, sqlx::Error> {
+    let row = sqlx::query!("SELECT channel, author, message, trigger_ids FROM alerts WHERE id = ?", id)
+        .fetch_optional(pool).await?;
+    Ok(row.map(|row| {
+        let mut message = TwitchMessageSimple::new(String::new(), row.channel, row.author, row.message);
+        message.trigger_ids = serde_json::from_str(&row.trigger_ids).unwrap_or_default();
+        message
+    }))
+}
+
+/// The alert's DM could not be sent, the outbox retries with a new alert
+pub async fn discard(pool: &sqlx::SqlitePool, id: i64) {
+    if let Err(e) = sqlx::query!("DELETE FROM alerts WHERE id = ?", id).execute(pool).await {
+        error!("[DS] Error discarding alert: {}", e);
+    }
+}
+
+/// Components with the Acknowledge button of alert `id`
+pub fn ack_components(components: &mut CreateComponents, id: i64) -> &mut CreateComponents {
+    components.create_action_row(|row|
+        row.create_button(|b|
+            b.custom_id(format!("{}{}", ACK_PREFIX, id))
+                .label("Acknowledge")
+                .style(ButtonStyle::Success)
+        )
+    )
+}
+
+/// Close alert `id` if `user_id` is allowed to, false if it's not open or not theirs
+pub async fn acknowledge(pool: &sqlx::SqlitePool, id: i64, user_id: UserId) -> Result<bool, sqlx::Error> {
+    let user_id = user_id.0 as i64;
+    let now = chrono::Utc::now().timestamp();
+    let res = sqlx::query!("UPDATE alerts SET status = 'acknowledged', closed_at = ?, acknowledged_by = ?
+            WHERE id = ? AND status = 'open' AND (discord_user_id = ? OR escalate_to = ?)",
+        now,
+        user_id,
+        id,
+        user_id,
+        user_id)
+        .execute(pool).await?;
+    if res.rows_affected() > 0 {
+        info!("[DS] Alert {} acknowledged by {}", id, user_id);
+    }
+    Ok(res.rows_affected() > 0)
+}
+
+/// Re-ping open alerts that are due, state lives in the `alerts` table so restarts don't lose it
+///
+/// Quiet hours and snoozes of everything or the alert's channel pause the re-pings (and the escalation),
+/// they resume once those are over.
+pub async fn run(pool: sqlx::SqlitePool, outbox: Outbox) {
+    let mut interval = tokio::time::interval(CHECK_EVERY);
+    loop {
+        interval.tick().await;
+        if let Err(e) = reping_due(&pool, &outbox).await {
+            error!("[DS] Error re-pinging alerts: {}", e);
+        }
+    }
+}
+
+async fn reping_due(pool: &sqlx::SqlitePool, outbox: &Outbox) -> Result<(), OutboxError> {
+    let now = chrono::Utc::now().timestamp();
+    let due = sqlx::query!(r#"SELECT id, discord_user_id, channel, author, message, repeat_minutes, escalate_to, pings,
+                EXISTS(SELECT 1 FROM settings WHERE settings.discord_user_id = alerts.escalate_to AND settings.accept_escalations) AS "escalation_accepted!: bool",
+                EXISTS(SELECT 1 FROM snoozes WHERE snoozes.discord_user_id = alerts.discord_user_id AND snoozes.until > ?
+                    AND (snoozes.channel = alerts.channel OR (snoozes.channel IS NULL AND snoozes.trigger_id IS NULL))) AS "snoozed!: bool"
+            FROM alerts WHERE status = 'open' AND next_ping_at <= ?"#,
+        now,
+        now)
+        .fetch_all(pool).await?;
+
+    for row in due {
+        if row.pings >= MAX_REPINGS {
+            info!("[DS] Giving up on alert {} after {} re-pings", row.id, row.pings);
+            sqlx::query!("UPDATE alerts SET status = 'expired', closed_at = ? WHERE id = ?", now, row.id)
+                .execute(pool).await?;
+            continue;
+        }
+        // Stays due without using up a re-ping, picked up again once the user isn't quiet or snoozed
+        if row.snoozed || quiet::quiet_now(&mut *pool.acquire().await?, row.discord_user_id).await?.is_some() {
+            continue;
+        }
+
+        let pings = row.pings + 1;
+        let next_ping_at = now + row.repeat_minutes * 60;
+        sqlx::query!("UPDATE alerts SET pings = ?, next_ping_at = ? WHERE id = ?", pings, next_ping_at, row.id)
+            .execute(pool).await?;
+
+        let alert = Alert {
+            id: row.id,
+            channel: row.channel,
+            author: row.author,
+            message: row.message,
+            pings,
+        };
+        outbox.push(TriggerEvent::Reping {
+            receiver: row.discord_user_id as u64,
+            alert: alert.clone(),
+            escalated_from: None,
+            timestamp: chrono::Utc::now(),
+        }).await?;
+        // The escalation user may have turned escalations off since
+        let escalate_to = row.escalate_to
+            .filter(|id| *id == row.discord_user_id || row.escalation_accepted);
+        if let Some(escalate_to) = escalate_to.filter(|_| pings >= ESCALATE_AFTER) {
+            outbox.push(TriggerEvent::Reping {
+                receiver: escalate_to as u64,
+                alert,
+                escalated_from: Some(row.discord_user_id as u64),
+                timestamp: chrono::Utc::now(),
+            }).await?;
+        }
+    }
+
+    let expired = now - CLOSED_RETENTION_SECS;
+    sqlx::query!("DELETE FROM alerts WHERE status != 'open' AND closed_at < ?", expired)
+        .execute(pool).await?;
+    Ok(())
+}
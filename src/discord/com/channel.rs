@@ -67,7 +67,7 @@ async fn channel(ctx: &Context, msg: &Message) -> CommandResult {
                         get_db!(ctx, db);
 
                         for channel in &channels {
-                            let res = sqlx::query!("SELECT EXISTS(SELECT 1 FROM channels WHERE channel = ?) AS result", channel)
+                            let res = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", channel)
                                 .fetch_one(&mut *db).await?;
                             let exists: bool = res.result == 1;
                             // debug!("ADD Channel #{} exists: {}", channel, exists);
@@ -162,7 +162,7 @@ async fn channel(ctx: &Context, msg: &Message) -> CommandResult {
                         get_db!(ctx, db);
 
                         for channel in &channels {
-                            let res = sqlx::query!("SELECT EXISTS(SELECT 1 FROM channels WHERE channel = ?) AS result", channel)
+                            let res = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", channel)
                                 .fetch_one(&mut *db).await?;
                             let exists: bool = res.result == 1;
                             // debug!("REMOVE Channel #{} exists: {}", channel, exists);
@@ -180,17 +180,42 @@ async fn channel(ctx: &Context, msg: &Message) -> CommandResult {
 
                     let rows = sqlx::query!("SELECT channel FROM channels WHERE discord_user_id = ?",
                         author_id)
-                        .fetch_all(db).await?;
-
-                    let mut channels = rows.iter().map(|row|
-                        format!("#{}", escape_twitch_channel(&row.channel))
-                    ).collect::<Vec<_>>();
+                        .fetch_all(&mut *db).await?;
+
+                    let now = chrono::Utc::now().timestamp();
+                    let snoozes = sqlx::query!("SELECT channel, until FROM snoozes WHERE discord_user_id = ? AND trigger_id IS NULL AND until > ?",
+                        author_id,
+                        now)
+                        .fetch_all(&mut *db).await?;
+                    let snoozed_all = snoozes.iter().find(|row| row.channel.is_none()).map(|row| row.until);
+
+                    let mut channels = rows.iter().map(|row| {
+                        let snoozed = snoozes.iter().find(|snooze| snooze.channel.as_ref() == Some(&row.channel));
+                        match snoozed {
+                            Some(snooze) => format!("#{} (💤 until <t:{}:t>)", escape_twitch_channel(&row.channel), snooze.until),
+                            None => format!("#{}", escape_twitch_channel(&row.channel)),
+                        }
+                    }).collect::<Vec<_>>();
                     channels.sort();
 
+                    let follows = sqlx::query!("SELECT channel, raided_from, expires_at FROM follows WHERE discord_user_id = ? AND expires_at > ?",
+                        author_id,
+                        now)
+                        .fetch_all(&mut *db).await?;
+                    for row in follows {
+                        channels.push(format!("#{} (raid from #{}, {} left)",
+                                              escape_twitch_channel(&row.channel),
+                                              escape_twitch_channel(&row.raided_from),
+                                              styled_str::fmt_duration(row.expires_at - now)));
+                    }
+
                     msg.channel_id.send_message(ctx, |m| {
                         m.embed(|e| {
                             e.title("Monitored channels");
                             e.description(channels.join(", "));
+                            if let Some(until) = snoozed_all {
+                                e.field("💤", format!("Everything snoozed until <t:{}:f>", until), false);
+                            }
                             e
                         });
                         m
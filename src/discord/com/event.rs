@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+use std::fmt::Write as _; // import without risk of name clashing
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::{Parser, Subcommand};
+use sqlx::{Acquire};
+
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::styled_str;
+use crate::styled_str::escape_twitch_channel;
+use crate::twitch::EventKind;
+
+/// Arguments to the event command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Action to perform
+    #[command(subcommand)]
+    action: Actions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Actions {
+    /// Subscribe to an event in your monitored channels
+    Add {
+        /// Kind of event
+        #[arg(value_enum)]
+        kind: EventKind,
+
+        /// Username to watch (only for `ban`)
+        username: Option<String>,
+    },
+    /// Remove event subscriptions
+    Remove {
+        /// IDs of the subscriptions to remove
+        ids: Vec<i64>,
+    },
+    /// List all event subscriptions
+    List,
+}
+
+#[group]
+#[commands(event)]
+struct Event;
+
+#[command]
+async fn event(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            match args.action {
+                Actions::Add { kind, username } => {
+                    let target = match (kind, username) {
+                        (EventKind::Ban, Some(username)) => username.to_lowercase(),
+                        (EventKind::Ban, None) => {
+                            msg.reply(ctx, "Specify the username to watch for bans and timeouts").await?;
+                            return Ok(());
+                        }
+                        (_, Some(_)) => {
+                            msg.reply(ctx, "Only `ban` takes a username").await?;
+                            return Ok(());
+                        }
+                        (_, None) => String::new(),
+                    };
+                    let kind = kind.as_str();
+
+                    get_db!(ctx, db);
+
+                    let res = sqlx::query!("INSERT INTO events (discord_user_id, kind, target) VALUES (?, ?, ?)",
+                        author_id,
+                        kind,
+                        target)
+                        .execute(db)
+                        .await;
+                    match res {
+                        Ok(_) => { msg.reply(ctx, format!("Subscribed to `{}` events", kind)).await?; }
+                        // SQLITE_CONSTRAINT_UNIQUE (UNIQUE constraint failed)
+                        Err(sqlx::Error::Database(e)) if e.code() == Some(Cow::Borrowed("2067")) => {
+                            msg.reply(ctx, "Already subscribed").await?;
+                        }
+                        Err(_) => { msg.reply(ctx, "Failed to subscribe").await?; }
+                    }
+                },
+                Actions::Remove { ids } => {
+                    get_db!(ctx, db);
+
+                    let mut subscriptions = sqlx::query!("SELECT id FROM events WHERE discord_user_id = ?",
+                        author_id)
+                        .fetch_all(&mut *db)
+                        .await?
+                        .into_iter()
+                        .map(|row| row.id)
+                        .collect::<Vec<i64>>();
+                    subscriptions.sort();
+
+                    let mut tx = db.begin().await?;
+                    for id in &ids {
+                        let Some(subscription_id) = usize::try_from(*id - 1).ok().and_then(|i| subscriptions.get(i)) else {
+                            tx.rollback().await?;
+                            msg.reply(ctx, format!("Failed to remove subscription: **{}**. Rollback.", id)).await?;
+                            return Ok(());
+                        };
+                        sqlx::query!("DELETE FROM events WHERE discord_user_id = ? AND id = ?",
+                            author_id,
+                            subscription_id)
+                            .execute(&mut tx)
+                            .await?;
+                    }
+                    tx.commit().await?;
+                    msg.reply(ctx, format!("Removed {} subscriptions", ids.len())).await?;
+                },
+                Actions::List => {
+                    get_db!(ctx, db);
+
+                    let rows = sqlx::query!("SELECT id, kind, target FROM events WHERE discord_user_id = ? ORDER BY id",
+                        author_id)
+                        .fetch_all(db)
+                        .await?;
+
+                    let mut reply = String::new();
+                    for (i, row) in (1..).zip(rows) {
+                        let _ = write!(reply, "**ID {}**: `{}`", i, row.kind);
+                        if !row.target.is_empty() {
+                            let _ = write!(reply, " {}", escape_twitch_channel(&row.target));
+                        }
+                        reply.push('\n');
+                    }
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Event subscriptions")
+                                .description(reply)
+                        )
+                    ).await?;
+                },
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fmt::Write as _; // import without risk of name clashing
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::Parser;
+
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::discord::gaps::fmt_gap;
+use crate::styled_str;
+
+/// Arguments to the gaps command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// How many of the latest gaps to show
+    #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=50))]
+    count: u8,
+}
+
+#[group]
+#[commands(gaps)]
+struct Gaps;
+
+#[command]
+async fn gaps(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    match args {
+        Ok(args) => {
+            let count = args.count as i64;
+            let rows = {
+                get_db!(ctx, db);
+                sqlx::query!("SELECT started_at, ended_at FROM gaps ORDER BY id DESC LIMIT ?", count)
+                    .fetch_all(db).await?
+            };
+
+            let mut reply = String::new();
+            for row in &rows {
+                let duration = row.ended_at.unwrap_or_else(|| chrono::Utc::now().timestamp()) - row.started_at;
+                let _ = writeln!(reply, "`{}` ({})",
+                                 fmt_gap(row.started_at, row.ended_at), styled_str::fmt_duration(duration));
+            }
+            if rows.is_empty() {
+                reply.push_str("No gaps recorded, chat was monitored without interruptions");
+            }
+
+            msg.channel_id.send_message(ctx, |m|
+                m.embed(|e|
+                    e.title("Monitoring gaps")
+                        .description(reply)
+                )
+            ).await?;
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
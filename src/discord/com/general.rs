@@ -55,15 +55,23 @@ async fn help(ctx: &Context, msg: &Message) -> CommandResult {
                 ("Channel", cmd_list!(
                      cmd!("channel add <channels>", "Add channels to watchlist"),
                      cmd!("channel remove <channels>", "Remove channels from watchlist"),
-                     cmd!("channel list", "List all channels in watchlist")
+                     cmd!("channel list", "List all channels in watchlist"),
+                     cmd!("recent <channel> [count] [--file]", "Show the latest chat lines of one of your channels"),
+                     cmd!("logs <channel> <from> <to>", "Get the chat log of one of your channels as a file (YYYY-MM-DD or YYYY-MM-DDTHH:MM, UTC, up to 7 days)")
                  ), false),
                 ("Trigger", cmd_list!(
                      // cmd!("trigger add `\\``<trigger>`\\`` ", "Add plaintext match trigger (ex: \"AzureDiamond\")"),
                      format!("```{}trigger add `<trigger>`\n```Add plaintext match trigger (ex: \"AzureDiamond\")\n", prefix).as_str(),
                     "`\t-r, --regex`\tAdd regex match trigger (ex: \"@is$|@is\\s\")\n",
                     "`\t           `\tThe regex flavour is Rust, see [docs](https://docs.rs/regex/latest/regex/#syntax), test [live](https://rustexp.lpil.uk/)\n",
-                    "`\t-c, --case-sensitive`\tMatch case-sensitive (default: case-insensitive)\n\n",
+                    "`\t-c, --case-sensitive`\tMatch case-sensitive (default: case-insensitive)\n",
+                    "`\t-b, --burst <count>`\tOnly notify once, when this many messages match within the window\n",
+                    "`\t-w, --window <secs>`\tBurst window in seconds (default: 30)\n",
+                    "`\t-d, --distinct`\tCount each chatter only once in a burst\n",
+                    "`\t-u, --urgent <minutes>`\tRe-ping every few minutes until the Acknowledge button is pressed, re-pings pause during quiet hours\n",
+                    "`\t-e, --escalate <user>`\tAlso ping this user when an urgent DM goes unacknowledged, they have to turn on `settings escalations` first\n\n",
                      cmd!("trigger remove <ids>", "Remove triggers with specified ids"),
+                     cmd!("trigger disable <ids>", "Pause triggers without removing them (`trigger enable <ids>` to resume)"),
                      cmd!("trigger list", "List all triggers and their ids")
                  ), false),
                 ("Ignore", cmd_list!(
@@ -71,6 +79,48 @@ async fn help(ctx: &Context, msg: &Message) -> CommandResult {
                      cmd!("ignore remove <usernames>", "Remove usernames from the list of ignored users"),
                      cmd!("ignore list", "List all usernames of ignored users")
                  ), false),
+                ("Event", cmd_list!(
+                     cmd!("event add <kind> [username]", "Get a DM on channel events: raid, gifts, announcement, ban <username>, modes"),
+                     cmd!("event remove <ids>", "Remove event subscriptions with specified ids"),
+                     cmd!("event list", "List all event subscriptions and their ids")
+                 ), false),
+                ("Presence", cmd_list!(
+                     cmd!("presence add <channel> <usernames>", "Get a DM when chatters join or leave one of your channels"),
+                     cmd!("presence remove <ids>", "Remove watched chatters with specified ids"),
+                     cmd!("presence list", "List all watched chatters and their ids")
+                 ), false),
+                ("Quiet hours", cmd_list!(
+                     cmd!("quiet add <start> <end> [-d <days>]", "No DMs between `HH:MM` and `HH:MM` in your timezone, days: all, weekdays, weekends, mon ... sun"),
+                     cmd!("quiet remove <ids>", "Remove quiet hours with specified ids"),
+                     cmd!("quiet list", "List all quiet hours and their ids")
+                 ), false),
+                ("Snooze", cmd_list!(
+                     cmd!("snooze <#channel|trigger <id>|all> <duration|until HH:MM>", "Mute a channel, a trigger or everything for a while (ex: `snooze #forsen 2h`)"),
+                     cmd!("unsnooze <#channel|trigger <id>|all>", "End snoozes early"),
+                     cmd!("snooze list", "List all snoozes")
+                 ), false),
+                ("Mirror (bot owner or guild admin)", cmd_list!(
+                     cmd!("mirror add <channel> <discord channel>", "Relay the whole chat of a Twitch channel to a guild channel"),
+                     cmd!("mirror remove <channel> <discord channel>", "Stop relaying"),
+                     cmd!("mirror list", "List the mirrors you set up")
+                 ), false),
+                ("Settings", cmd_list!(
+                     cmd!("settings gaps <on|off>", "Get a DM after chat monitoring had a gap"),
+                     cmd!("settings deleted <keep|edit|remove>", "What to do with a DM when moderators delete the message"),
+                     cmd!("settings raids <minutes>", "Follow raids from your channels for a while (0 to turn off), outgoing raids are detected for at most 10 channels across all users"),
+                     cmd!("settings followup <seconds>", "Forward the next messages of whoever triggered a DM (0 to turn off)"),
+                     cmd!("settings context <lines> [delay]", "Attach chat lines before the trigger, and after it when waiting `delay` seconds"),
+                     cmd!("settings cooldown <seconds>", "Wait between two DMs, the next one says how many were held back (0 to turn off)"),
+                     cmd!("settings trigger-cooldown <seconds>", "Wait between two DMs of the same trigger (0 to turn off)"),
+                     cmd!("settings duplicates <seconds>", "Drop near-identical messages of the same author for a while (0 to turn off)"),
+                     cmd!("settings coalesce <seconds>", "Add matches to the last DM of a channel until it's quiet this long (0 to turn off)"),
+                     cmd!("settings delivery <instant|digest|both> [minutes]", "Get a DM for every match, a digest every `minutes` (60 by default), or both"),
+                     cmd!("settings timezone <timezone>", "Your timezone for quiet hours, like `Europe/Berlin`"),
+                     cmd!("settings quiet <hold|drop>", "Hold DMs during quiet hours for a digest when they end, or drop them"),
+                     cmd!("settings escalations <on|off>", "Let other users name you in `trigger add --escalate`"),
+                     cmd!("settings list", "List all settings"),
+                     cmd!("gaps [count]", "List the latest monitoring gaps")
+                 ), false),
             ]);
             e
         });
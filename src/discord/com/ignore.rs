@@ -58,10 +58,7 @@ async fn ignore(ctx: &Context, msg: &Message) -> CommandResult {
 
                     let mut tx = db.begin().await?;
                     for username in &usernames {
-                        let res = sqlx::query!("INSERT OR IGNORE INTO ignores (discord_user_id, username) VALUES (?, ?)",
-                            author_id,
-                            username)
-                            .execute(&mut tx).await;
+                        let res = crate::db::add_ignore(&mut tx, author_id, username).await;
                         if let Err(e) = res {
                             match e {
                                 sqlx::Error::Database(e) => {
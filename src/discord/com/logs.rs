@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::Parser;
+
+use crate::discord::{ChatLogs, CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::styled_str;
+use crate::styled_str::escape_twitch_channel;
+use crate::twitch::chatlog;
+
+/// Discord's attachment limit for bots without boosts
+const MAX_ATTACHMENT_BYTES: usize = 8 * 1024 * 1024;
+
+/// Arguments to the logs command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// One of your monitored channels
+    channel: String,
+
+    /// Start of the range, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM` (UTC)
+    #[arg(value_parser = parse_from)]
+    from: DateTime<Utc>,
+
+    /// End of the range (inclusive), same format
+    #[arg(value_parser = parse_to)]
+    to: DateTime<Utc>,
+}
+
+fn parse_time(s: &str, day_time: NaiveTime) -> Result<DateTime<Utc>, String> {
+    let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|date| date.and_time(day_time)))
+        .map_err(|_| "expected YYYY-MM-DD or YYYY-MM-DDTHH:MM".to_string())?;
+    Ok(Utc.from_utc_datetime(&time))
+}
+
+fn parse_from(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_time(s, NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+}
+
+fn parse_to(s: &str) -> Result<DateTime<Utc>, String> {
+    // A whole day when only the date is given, a whole minute otherwise
+    let time = parse_time(s, NaiveTime::from_hms_opt(23, 59, 59).unwrap())?;
+    Ok(if s.contains('T') { time + chrono::Duration::seconds(59) } else { time })
+}
+
+#[group]
+#[commands(logs)]
+struct Logs;
+
+#[command]
+async fn logs(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            let Some(settings) = ({
+                let data = ctx.data.read().await;
+                data.get::<ChatLogs>().unwrap().clone()
+            }) else {
+                msg.reply(ctx, "Chat logs are not enabled on this bot").await?;
+                return Ok(());
+            };
+
+            let channel = args.channel.trim_start_matches('#').to_lowercase();
+            // Also keeps the path inside the log directory
+            if !channel.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                msg.reply(ctx, "Not a valid channel name").await?;
+                return Ok(());
+            }
+            if args.from > args.to {
+                msg.reply(ctx, "The range ends before it starts").await?;
+                return Ok(());
+            }
+            if args.to - args.from > chrono::Duration::days(chatlog::MAX_RANGE_DAYS) {
+                msg.reply(ctx, format!("The range can't be longer than {} days", chatlog::MAX_RANGE_DAYS)).await?;
+                return Ok(());
+            }
+
+            let watched = {
+                get_db!(ctx, db);
+                sqlx::query!("SELECT EXISTS(SELECT 1 FROM watched WHERE discord_user_id = ? AND channel = ?) AS result",
+                    author_id,
+                    channel)
+                    .fetch_one(db).await?
+                    .result == 1
+            };
+            if !watched {
+                msg.reply(ctx, format!("Channel {} is not in your list", escape_twitch_channel(&channel))).await?;
+                return Ok(());
+            }
+
+            let text = chatlog::read_range(&settings, &channel, args.from, args.to).await?;
+            if text.is_empty() {
+                msg.reply(ctx, "Nothing logged in that range").await?;
+                return Ok(());
+            }
+            if text.len() > MAX_ATTACHMENT_BYTES {
+                msg.reply(ctx, "Too much chat in that range, try a shorter one").await?;
+                return Ok(());
+            }
+
+            msg.channel_id.send_message(ctx, |m|
+                m.content(format!("Chat log of {} from {} to {} (UTC)",
+                                  escape_twitch_channel(&channel),
+                                  args.from.format(chatlog::LINE_TIME_FORMAT),
+                                  args.to.format(chatlog::LINE_TIME_FORMAT)))
+                    .add_file(AttachmentType::Bytes {
+                        data: Cow::Owned(text.into_bytes()),
+                        filename: format!("{}_{}_{}.log", channel, args.from.format("%Y%m%d%H%M"), args.to.format("%Y%m%d%H%M")),
+                    })
+            ).await?;
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
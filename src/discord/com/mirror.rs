@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use std::fmt::Write as _; // import without risk of name clashing
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+use serenity::utils::parse_channel;
+
+use clap::{Parser, Subcommand};
+
+use crate::discord::{owner_id, CommandPrefix, DbConnection, IrcEventSender};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::twitch::{make_join_msg, make_part_msg};
+use crate::styled_str;
+use crate::styled_str::escape_twitch_channel;
+
+/// Arguments to the mirror command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Action to perform
+    #[command(subcommand)]
+    action: Actions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Actions {
+    /// Relay the whole chat of a Twitch channel to a guild channel
+    Add {
+        /// Twitch channel
+        channel: String,
+
+        /// Guild channel (mention or ID)
+        discord_channel: String,
+    },
+    /// Stop relaying a Twitch channel to a guild channel
+    Remove {
+        /// Twitch channel
+        channel: String,
+
+        /// Guild channel (mention or ID)
+        discord_channel: String,
+    },
+    /// List the mirrors you set up (all of them for the bot owner)
+    List,
+}
+
+#[group]
+#[commands(mirror)]
+struct Mirror;
+
+#[command]
+async fn mirror(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let irc_tx = {
+        let data = ctx.data.read().await;
+        data.get::<IrcEventSender>().unwrap().clone()
+    };
+
+    let author_id = msg.author.id.0 as i64;
+    let is_owner = owner_id() == Some(msg.author.id);
+
+    match args {
+        Ok(args) => {
+            match args.action {
+                Actions::Add { channel, discord_channel } => {
+                    let channel = channel.trim_start_matches('#').to_lowercase();
+                    let Some(guild_channel) = manageable_channel(ctx, msg.author.id, is_owner, &discord_channel).await else {
+                        msg.reply(ctx, "Not a guild text channel you administer").await?;
+                        return Ok(());
+                    };
+                    let discord_channel_id = guild_channel.id.0 as i64;
+                    let guild_id = guild_channel.guild_id.0 as i64;
+
+                    get_db!(ctx, db);
+
+                    let joined = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", channel)
+                        .fetch_one(&mut *db).await?
+                        .result == 1;
+
+                    let res = sqlx::query!("INSERT INTO mirrors (channel, discord_channel_id, guild_id, created_by) VALUES (?, ?, ?, ?)",
+                        channel,
+                        discord_channel_id,
+                        guild_id,
+                        author_id)
+                        .execute(&mut *db)
+                        .await;
+                    match res {
+                        Ok(_) => {
+                            if !joined {
+                                irc_tx.send(make_join_msg(channel.clone())).await?;
+                            }
+                            msg.reply(ctx, format!("Mirroring {} to {}", escape_twitch_channel(&channel), guild_channel.id.mention())).await?;
+                        }
+                        // SQLITE_CONSTRAINT_UNIQUE (UNIQUE constraint failed)
+                        Err(sqlx::Error::Database(e)) if e.code() == Some(Cow::Borrowed("2067")) => {
+                            msg.reply(ctx, "Already mirrored there").await?;
+                        }
+                        Err(_) => { msg.reply(ctx, "Failed to add mirror").await?; }
+                    }
+                },
+                Actions::Remove { channel, discord_channel } => {
+                    let channel = channel.trim_start_matches('#').to_lowercase();
+                    let Some(guild_channel) = manageable_channel(ctx, msg.author.id, is_owner, &discord_channel).await else {
+                        msg.reply(ctx, "Not a guild text channel you administer").await?;
+                        return Ok(());
+                    };
+                    let discord_channel_id = guild_channel.id.0 as i64;
+
+                    get_db!(ctx, db);
+
+                    let res = sqlx::query!("DELETE FROM mirrors WHERE channel = ? AND discord_channel_id = ?",
+                        channel,
+                        discord_channel_id)
+                        .execute(&mut *db)
+                        .await?;
+                    if res.rows_affected() == 0 {
+                        msg.reply(ctx, "No such mirror").await?;
+                        return Ok(());
+                    }
+
+                    let joined = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", channel)
+                        .fetch_one(&mut *db).await?
+                        .result == 1;
+                    if !joined {
+                        irc_tx.send(make_part_msg(channel.clone())).await?;
+                    }
+                    msg.reply(ctx, format!("Stopped mirroring {} to {}", escape_twitch_channel(&channel), guild_channel.id.mention())).await?;
+                },
+                Actions::List => {
+                    get_db!(ctx, db);
+
+                    let rows = sqlx::query!("SELECT channel, discord_channel_id FROM mirrors WHERE created_by = ? OR ? ORDER BY id",
+                        author_id,
+                        is_owner)
+                        .fetch_all(db)
+                        .await?;
+
+                    let mut reply = String::new();
+                    for row in &rows {
+                        let _ = writeln!(reply, "{} → {}",
+                            escape_twitch_channel(&row.channel),
+                            ChannelId(row.discord_channel_id as u64).mention());
+                    }
+                    if rows.is_empty() {
+                        reply.push_str("No mirrors");
+                    }
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Mirrors")
+                                .description(reply)
+                        )
+                    ).await?;
+                },
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
+
+/// The guild text channel, if the user is the bot owner or an administrator of its guild
+async fn manageable_channel(ctx: &Context, user_id: UserId, is_owner: bool, discord_channel: &str) -> Option<GuildChannel> {
+    let channel_id = parse_channel(discord_channel).or_else(|| discord_channel.parse().ok())?;
+    let Ok(Channel::Guild(guild_channel)) = ChannelId(channel_id).to_channel(ctx).await else {
+        return None;
+    };
+    if guild_channel.kind != ChannelType::Text {
+        return None;
+    }
+    if is_owner {
+        return Some(guild_channel);
+    }
+
+    let guild = guild_channel.guild_id.to_guild_cached(&ctx.cache)?;
+    let permissions = guild.member_permissions(ctx, user_id).await.ok()?;
+    permissions.administrator().then_some(guild_channel)
+}
@@ -3,11 +3,29 @@ mod general;
 mod channel;
 mod trigger;
 mod ignore;
+mod settings;
+mod gaps;
+mod event;
+mod presence;
+mod quiet;
+mod snooze;
+mod recent;
+mod logs;
+mod mirror;
 
 pub use general::GENERAL_GROUP;
 pub use channel::CHANNEL_GROUP;
 pub use trigger::TRIGGER_GROUP;
 pub use ignore::IGNORE_GROUP;
+pub use settings::SETTINGS_GROUP;
+pub use gaps::GAPS_GROUP;
+pub use event::EVENT_GROUP;
+pub use presence::PRESENCE_GROUP;
+pub use quiet::QUIET_GROUP;
+pub use snooze::SNOOZE_GROUP;
+pub use recent::RECENT_GROUP;
+pub use logs::LOGS_GROUP;
+pub use mirror::MIRROR_GROUP;
 
 
 macro_rules! get_db {
@@ -44,7 +62,7 @@ macro_rules! update_channel_count {
                 data.insert::<ChannelCount>(channel_count);
             }
 
-            $ctx.set_activity(Activity::watching(format!("{} chats | DM {}help", channel_count, prefix))).await;
+            $ctx.set_activity(crate::discord::make_activity(channel_count, &prefix)).await;
         }
     };
 }
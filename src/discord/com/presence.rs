@@ -0,0 +1,142 @@
+use std::fmt::Write as _; // import without risk of name clashing
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::{Parser, Subcommand};
+use sqlx::{Acquire};
+
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::styled_str;
+use crate::styled_str::escape_twitch_channel;
+
+/// Arguments to the presence command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Action to perform
+    #[command(subcommand)]
+    action: Actions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Actions {
+    /// Get a DM when chatters join or leave one of your monitored channels
+    Add {
+        /// Monitored channel
+        channel: String,
+
+        /// Chatters to watch
+        #[arg(required = true)]
+        usernames: Vec<String>,
+    },
+    /// Remove watched chatters
+    Remove {
+        /// IDs of the chatters to remove
+        ids: Vec<i64>,
+    },
+    /// List all watched chatters
+    List,
+}
+
+#[group]
+#[commands(presence)]
+struct Presence;
+
+#[command]
+async fn presence(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            match args.action {
+                Actions::Add { channel, usernames } => {
+                    let channel = channel.trim_start_matches('#').to_lowercase();
+
+                    get_db!(ctx, db);
+
+                    let res = sqlx::query!("SELECT EXISTS(SELECT 1 FROM watched WHERE discord_user_id = ? AND channel = ?) AS result",
+                        author_id,
+                        channel)
+                        .fetch_one(&mut *db).await?;
+                    if res.result != 1 {
+                        msg.reply(ctx, format!("Channel {} is not in your list", escape_twitch_channel(&channel))).await?;
+                        return Ok(());
+                    }
+
+                    let mut tx = db.begin().await?;
+                    for username in &usernames {
+                        let username = username.to_lowercase();
+                        sqlx::query!("INSERT OR IGNORE INTO presence (discord_user_id, channel, username) VALUES (?, ?, ?)",
+                            author_id,
+                            channel,
+                            username)
+                            .execute(&mut tx)
+                            .await?;
+                    }
+                    tx.commit().await?;
+                    msg.reply(ctx, format!("Watching {} chatters in {}", usernames.len(), escape_twitch_channel(&channel))).await?;
+                },
+                Actions::Remove { ids } => {
+                    get_db!(ctx, db);
+
+                    let mut watched = sqlx::query!("SELECT id FROM presence WHERE discord_user_id = ?",
+                        author_id)
+                        .fetch_all(&mut *db)
+                        .await?
+                        .into_iter()
+                        .map(|row| row.id)
+                        .collect::<Vec<i64>>();
+                    watched.sort();
+
+                    let mut tx = db.begin().await?;
+                    for id in &ids {
+                        let Some(presence_id) = usize::try_from(*id - 1).ok().and_then(|i| watched.get(i)) else {
+                            tx.rollback().await?;
+                            msg.reply(ctx, format!("Failed to remove chatter: **{}**. Rollback.", id)).await?;
+                            return Ok(());
+                        };
+                        sqlx::query!("DELETE FROM presence WHERE discord_user_id = ? AND id = ?",
+                            author_id,
+                            presence_id)
+                            .execute(&mut tx)
+                            .await?;
+                    }
+                    tx.commit().await?;
+                    msg.reply(ctx, format!("Removed {} chatters", ids.len())).await?;
+                },
+                Actions::List => {
+                    get_db!(ctx, db);
+
+                    let rows = sqlx::query!("SELECT channel, username FROM presence WHERE discord_user_id = ? ORDER BY id",
+                        author_id)
+                        .fetch_all(db)
+                        .await?;
+
+                    let mut reply = String::new();
+                    for (i, row) in (1..).zip(rows) {
+                        let _ = writeln!(reply, "**ID {}**: {} in #{}", i,
+                            escape_twitch_channel(&row.username),
+                            escape_twitch_channel(&row.channel));
+                    }
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Watched chatters")
+                                .description(reply)
+                        )
+                    ).await?;
+                },
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
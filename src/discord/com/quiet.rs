@@ -0,0 +1,148 @@
+use std::fmt::Write as _; // import without risk of name clashing
+use chrono::{NaiveTime, Timelike};
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::{Parser, Subcommand};
+use sqlx::{Acquire};
+
+use crate::db::QuietDays;
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::discord::quiet::{fmt_minute, quiet_now};
+use crate::styled_str;
+
+/// Arguments to the quiet command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Action to perform
+    #[command(subcommand)]
+    action: Actions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Actions {
+    /// Add quiet hours, in your timezone (see `settings timezone`)
+    Add {
+        /// Start, `HH:MM`
+        #[arg(value_parser = parse_minute)]
+        start: i64,
+
+        /// End, `HH:MM`, before the start to go past midnight
+        #[arg(value_parser = parse_minute)]
+        end: i64,
+
+        /// Days the quiet hours start on
+        #[arg(short, long, value_enum, default_value_t = QuietDays::All)]
+        days: QuietDays,
+    },
+    /// Remove quiet hours
+    Remove {
+        /// IDs of the quiet hours to remove
+        ids: Vec<i64>,
+    },
+    /// List all quiet hours
+    List,
+}
+
+/// Minute of the day from `HH:MM`
+fn parse_minute(s: &str) -> Result<i64, String> {
+    let time = NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|_| "expected HH:MM".to_string())?;
+    Ok((time.hour() * 60 + time.minute()) as i64)
+}
+
+#[group]
+#[commands(quiet)]
+struct Quiet;
+
+#[command]
+async fn quiet(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            match args.action {
+                Actions::Add { start, end, days } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO quiet_hours (discord_user_id, days, start_minute, end_minute) VALUES (?, ?, ?, ?)",
+                        author_id,
+                        days,
+                        start,
+                        end)
+                        .execute(db)
+                        .await?;
+                    msg.reply(ctx, format!("Quiet hours: {}–{} ({})", fmt_minute(start), fmt_minute(end), days.as_str())).await?;
+                },
+                Actions::Remove { ids } => {
+                    get_db!(ctx, db);
+
+                    let mut windows = sqlx::query!("SELECT id FROM quiet_hours WHERE discord_user_id = ?",
+                        author_id)
+                        .fetch_all(&mut *db)
+                        .await?
+                        .into_iter()
+                        .map(|row| row.id)
+                        .collect::<Vec<i64>>();
+                    windows.sort();
+
+                    let mut tx = db.begin().await?;
+                    for id in &ids {
+                        let Some(window_id) = usize::try_from(*id - 1).ok().and_then(|i| windows.get(i)) else {
+                            tx.rollback().await?;
+                            msg.reply(ctx, format!("Failed to remove quiet hours: **{}**. Rollback.", id)).await?;
+                            return Ok(());
+                        };
+                        sqlx::query!("DELETE FROM quiet_hours WHERE discord_user_id = ? AND id = ?",
+                            author_id,
+                            window_id)
+                            .execute(&mut tx)
+                            .await?;
+                    }
+                    tx.commit().await?;
+                    msg.reply(ctx, format!("Removed {} quiet hours", ids.len())).await?;
+                },
+                Actions::List => {
+                    get_db!(ctx, db);
+
+                    let rows = sqlx::query!(r#"SELECT days AS "days: QuietDays", start_minute, end_minute FROM quiet_hours WHERE discord_user_id = ? ORDER BY id"#,
+                        author_id)
+                        .fetch_all(&mut *db)
+                        .await?;
+                    let settings = crate::db::get_settings(db, author_id).await?;
+                    let quiet = quiet_now(db, author_id).await?;
+
+                    let mut reply = String::new();
+                    for (i, row) in (1..).zip(rows) {
+                        let _ = writeln!(reply, "**ID {}**: {}–{} ({})", i,
+                            fmt_minute(row.start_minute),
+                            fmt_minute(row.end_minute),
+                            row.days.as_str());
+                    }
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Quiet hours")
+                                .description(reply)
+                                .footer(|f| f.text(format!("{} ∙ {} ∙ {}",
+                                    settings.timezone,
+                                    settings.quiet_action.as_str(),
+                                    if quiet.is_some() { "quiet now" } else { "not quiet now" })))
+                        )
+                    ).await?;
+                },
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::Parser;
+
+use crate::discord::{CommandPrefix, DbConnection, RecentChat};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::styled_str;
+use crate::styled_str::{escape_twitch_channel, escape_twitch_message};
+use crate::twitch::ChatLine;
+
+/// Longest reply sent as embeds, more becomes a text attachment
+const MAX_PAGES: usize = 3;
+/// Embed description limit, with some room to spare
+const PAGE_CHARS: usize = 4000;
+
+/// Arguments to the recent command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// One of your monitored channels
+    channel: String,
+
+    /// How many of the latest chat lines to show
+    #[arg(default_value_t = 20, value_parser = clap::value_parser!(u8).range(1..=100))]
+    count: u8,
+
+    /// Send the lines as a text file
+    #[arg(short, long, default_value_t = false)]
+    file: bool,
+}
+
+#[group]
+#[commands(recent)]
+struct Recent;
+
+#[command]
+async fn recent(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            let channel = args.channel.trim_start_matches('#').to_lowercase();
+
+            let watched = {
+                get_db!(ctx, db);
+                sqlx::query!("SELECT EXISTS(SELECT 1 FROM watched WHERE discord_user_id = ? AND channel = ?) AS result",
+                    author_id,
+                    channel)
+                    .fetch_one(db).await?
+                    .result == 1
+            };
+            if !watched {
+                msg.reply(ctx, format!("Channel {} is not in your list", escape_twitch_channel(&channel))).await?;
+                return Ok(());
+            }
+
+            let lines = {
+                let data = ctx.data.read().await;
+                data.get::<RecentChat>().unwrap().last(&channel, args.count as usize)
+            };
+            if lines.is_empty() {
+                msg.reply(ctx, format!("Nothing said in {} since the bot started", escape_twitch_channel(&channel))).await?;
+                return Ok(());
+            }
+
+            let pages = paginate(&lines);
+            if args.file || pages.len() > MAX_PAGES {
+                let text = lines.iter()
+                    .map(|line| format!("[{}] {}: {}", line.timestamp.format("%H:%M:%S"), line.author, line.message))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                msg.channel_id.send_message(ctx, |m|
+                    m.content(format!("Last {} lines in {}", lines.len(), escape_twitch_channel(&channel)))
+                        .add_file(AttachmentType::Bytes {
+                            data: Cow::Owned(text.into_bytes()),
+                            filename: format!("{}.txt", channel),
+                        })
+                ).await?;
+                return Ok(());
+            }
+
+            let page_count = pages.len();
+            for (i, page) in (1..).zip(pages) {
+                msg.channel_id.send_message(ctx, |m|
+                    m.embed(|e| {
+                        e.title(format!("Recent chat in #{}", channel))
+                            .url(format!("https://twitch.tv/{}", channel))
+                            .description(page);
+                        if page_count > 1 {
+                            e.footer(|f| f.text(format!("Page {}/{}", i, page_count)));
+                        }
+                        e
+                    })
+                ).await?;
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Split the lines into embed descriptions
+fn paginate(lines: &[ChatLine]) -> Vec<String> {
+    let mut pages = vec![String::new()];
+    for line in lines {
+        let line = format!("`{}` **{}**: {}\n",
+                           line.timestamp.format("%H:%M:%S"),
+                           escape_twitch_channel(&line.author),
+                           escape_twitch_message(&line.message));
+        let page = pages.last_mut().unwrap();
+        if !page.is_empty() && page.chars().count() + line.chars().count() > PAGE_CHARS {
+            pages.push(line);
+        } else {
+            page.push_str(&line);
+        }
+    }
+    pages
+}
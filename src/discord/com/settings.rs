@@ -0,0 +1,383 @@
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::{ArgAction, Parser, Subcommand};
+use clap::builder::BoolishValueParser;
+
+use crate::db::{DeliveryMode, OnDelete, QuietAction};
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::discord::extra::IntoEmoji;
+use crate::styled_str;
+use crate::twitch::MAX_WINDOW_SECS;
+use crate::twitch::eventsub::{EventSubSettings, RAID_CHANNELS_MAX};
+
+/// Arguments to the settings command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Action to perform
+    #[command(subcommand)]
+    action: Actions,
+}
+
+#[derive(Subcommand, Debug)]
+enum Actions {
+    /// Get a DM after chat monitoring had a gap (on/off)
+    Gaps {
+        #[arg(value_parser = BoolishValueParser::new(), action = ArgAction::Set)]
+        enabled: bool,
+    },
+    /// What to do with a DM when moderators delete the message
+    Deleted {
+        #[arg(value_enum)]
+        action: OnDelete,
+    },
+    /// Follow raids from monitored channels for this many minutes (0 to not follow),
+    /// raids are only detected for a limited number of channels across all users
+    Raids {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=720))]
+        minutes: u16,
+    },
+    /// Forward the next messages of an author for this many seconds after a trigger (0 to not forward)
+    Followup {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=600))]
+        seconds: u16,
+    },
+    /// Attach this many chat lines before the trigger (0 to not attach)
+    Context {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=10))]
+        lines: u8,
+
+        /// Also wait this many seconds for the lines after the trigger
+        #[arg(default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=30))]
+        delay: u8,
+    },
+    /// Wait at least this many seconds between two notifications (0 to not wait)
+    Cooldown {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=MAX_WINDOW_SECS as i64))]
+        seconds: u16,
+    },
+    /// Wait at least this many seconds between two notifications of the same trigger (0 to not wait)
+    TriggerCooldown {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=MAX_WINDOW_SECS as i64))]
+        seconds: u16,
+    },
+    /// Drop near-identical messages of the same author within this many seconds (0 to keep them)
+    Duplicates {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=MAX_WINDOW_SECS as i64))]
+        seconds: u16,
+    },
+    /// Get a DM for every match, periodic digests, or both
+    Delivery {
+        #[arg(value_enum)]
+        mode: DeliveryMode,
+
+        /// Minutes between digests
+        #[arg(default_value_t = 60, value_parser = clap::value_parser!(u16).range(5..=1440))]
+        minutes: u16,
+    },
+    /// Add matches to the last DM of a channel until it's quiet for this many seconds (0 for a DM each)
+    Coalesce {
+        #[arg(value_parser = clap::value_parser!(u16).range(0..=MAX_WINDOW_SECS as i64))]
+        seconds: u16,
+    },
+    /// Your timezone for quiet hours, like `Europe/Berlin`
+    Timezone {
+        #[arg(value_parser = parse_timezone)]
+        timezone: chrono_tz::Tz,
+    },
+    /// Hold notifications during quiet hours for a digest when they end, or drop them
+    Quiet {
+        #[arg(value_enum)]
+        action: QuietAction,
+    },
+    /// Let other users ping you when their urgent DMs go unacknowledged (on/off)
+    Escalations {
+        #[arg(value_parser = BoolishValueParser::new(), action = ArgAction::Set)]
+        enabled: bool,
+    },
+    /// List all settings
+    List,
+}
+
+fn parse_timezone(s: &str) -> Result<chrono_tz::Tz, String> {
+    s.parse().map_err(|_| "expected a timezone like `Europe/Berlin` or `UTC`".to_string())
+}
+
+#[group]
+#[commands(settings)]
+struct Settings;
+
+#[command]
+async fn settings(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(msg.content.trim_start_matches(&prefix).split_whitespace());
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            match args.action {
+                Actions::Gaps { enabled } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, gap_notices) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET gap_notices = excluded.gap_notices",
+                        author_id,
+                        enabled)
+                        .execute(db).await?;
+
+                    msg.reply(ctx, format!("Gap notices: {}", enabled.emoji())).await?;
+                },
+                Actions::Deleted { action } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, on_delete) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET on_delete = excluded.on_delete",
+                        author_id,
+                        action)
+                        .execute(db).await?;
+
+                    msg.reply(ctx, format!("Deleted messages: `{}`", action.as_str())).await?;
+                },
+                Actions::Raids { minutes } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, raid_follow_minutes) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET raid_follow_minutes = excluded.raid_follow_minutes",
+                        author_id,
+                        minutes)
+                        .execute(&mut *db).await?;
+
+                    if minutes == 0 {
+                        msg.reply(ctx, "Raids: not followed").await?;
+                    } else {
+                        let reply = format!("Raids: followed for {} minutes", minutes);
+                        let reply = match EventSubSettings::from_env() {
+                            Ok(Some(_)) => {
+                                let refused = sqlx::query!("SELECT channels.channel FROM channels INNER JOIN raid_subscriptions ON raid_subscriptions.channel = channels.channel
+                                        WHERE channels.discord_user_id = ? AND raid_subscriptions.status = 'refused' ORDER BY channels.channel",
+                                    author_id)
+                                    .fetch_all(db).await?
+                                    .into_iter()
+                                    .map(|row| format!("#{}", styled_str::escape_twitch_channel(&row.channel)))
+                                    .collect::<Vec<_>>();
+                                if refused.is_empty() {
+                                    reply
+                                } else {
+                                    format!("{}, except from {}: Twitch refused to report their raids (at most {} channels across all users)",
+                                            reply, refused.join(", "), RAID_CHANNELS_MAX)
+                                }
+                            }
+                            _ => format!("{}, but only when the raid shows up in a chat the bot is already in (raid detection through EventSub is off on this bot)", reply),
+                        };
+                        msg.reply(ctx, reply).await?;
+                    }
+                },
+                Actions::Followup { seconds } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, followup_secs) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET followup_secs = excluded.followup_secs",
+                        author_id,
+                        seconds)
+                        .execute(db).await?;
+
+                    if seconds == 0 {
+                        msg.reply(ctx, "Follow-up: off").await?;
+                    } else {
+                        msg.reply(ctx, format!("Follow-up: next messages forwarded for {} seconds", seconds)).await?;
+                    }
+                },
+                Actions::Context { lines, delay } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, context_lines, context_delay_secs) VALUES (?, ?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET context_lines = excluded.context_lines, context_delay_secs = excluded.context_delay_secs",
+                        author_id,
+                        lines,
+                        delay)
+                        .execute(db).await?;
+
+                    match (lines, delay) {
+                        (0, _) => msg.reply(ctx, "Context: off").await?,
+                        (lines, 0) => msg.reply(ctx, format!("Context: {} lines before", lines)).await?,
+                        (lines, delay) => msg.reply(ctx, format!("Context: {} lines before and after (waiting {} seconds)", lines, delay)).await?,
+                    };
+                },
+                Actions::Cooldown { seconds } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, cooldown_secs) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET cooldown_secs = excluded.cooldown_secs",
+                        author_id,
+                        seconds)
+                        .execute(db).await?;
+
+                    if seconds == 0 {
+                        msg.reply(ctx, "Cooldown: off").await?;
+                    } else {
+                        msg.reply(ctx, format!("Cooldown: {} seconds between notifications", seconds)).await?;
+                    }
+                },
+                Actions::TriggerCooldown { seconds } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, trigger_cooldown_secs) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET trigger_cooldown_secs = excluded.trigger_cooldown_secs",
+                        author_id,
+                        seconds)
+                        .execute(db).await?;
+
+                    if seconds == 0 {
+                        msg.reply(ctx, "Trigger cooldown: off").await?;
+                    } else {
+                        msg.reply(ctx, format!("Trigger cooldown: {} seconds between notifications of a trigger", seconds)).await?;
+                    }
+                },
+                Actions::Duplicates { seconds } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, duplicate_secs) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET duplicate_secs = excluded.duplicate_secs",
+                        author_id,
+                        seconds)
+                        .execute(db).await?;
+
+                    if seconds == 0 {
+                        msg.reply(ctx, "Duplicates: kept").await?;
+                    } else {
+                        msg.reply(ctx, format!("Duplicates: dropped within {} seconds", seconds)).await?;
+                    }
+                },
+                Actions::Delivery { mode, minutes } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, delivery_mode, digest_minutes) VALUES (?, ?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET delivery_mode = excluded.delivery_mode, digest_minutes = excluded.digest_minutes",
+                        author_id,
+                        mode,
+                        minutes)
+                        .execute(db).await?;
+
+                    match mode {
+                        DeliveryMode::Instant => msg.reply(ctx, "Delivery: a DM for every match").await?,
+                        DeliveryMode::Digest => msg.reply(ctx, format!("Delivery: a digest every {} minutes", minutes)).await?,
+                        DeliveryMode::Both => msg.reply(ctx, format!("Delivery: a DM for every match and a digest every {} minutes", minutes)).await?,
+                    };
+                },
+                Actions::Coalesce { seconds } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, coalesce_secs) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET coalesce_secs = excluded.coalesce_secs",
+                        author_id,
+                        seconds)
+                        .execute(db).await?;
+
+                    if seconds == 0 {
+                        msg.reply(ctx, "Coalesce: a DM for every match").await?;
+                    } else {
+                        msg.reply(ctx, format!("Coalesce: matches added to the last DM of a channel until it's quiet for {} seconds", seconds)).await?;
+                    }
+                },
+                Actions::Timezone { timezone } => {
+                    get_db!(ctx, db);
+
+                    let name = timezone.name();
+                    sqlx::query!("INSERT INTO settings (discord_user_id, timezone) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET timezone = excluded.timezone",
+                        author_id,
+                        name)
+                        .execute(db).await?;
+
+                    let now = chrono::Utc::now().with_timezone(&timezone);
+                    msg.reply(ctx, format!("Timezone: `{}` (it's {} there)", name, now.format("%H:%M"))).await?;
+                },
+                Actions::Quiet { action } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, quiet_action) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET quiet_action = excluded.quiet_action",
+                        author_id,
+                        action)
+                        .execute(db).await?;
+
+                    match action {
+                        QuietAction::Hold => msg.reply(ctx, "Quiet hours: notifications held for a digest when they end").await?,
+                        QuietAction::Drop => msg.reply(ctx, "Quiet hours: notifications dropped").await?,
+                    };
+                },
+                Actions::Escalations { enabled } => {
+                    get_db!(ctx, db);
+
+                    sqlx::query!("INSERT INTO settings (discord_user_id, accept_escalations) VALUES (?, ?)
+                            ON CONFLICT(discord_user_id) DO UPDATE SET accept_escalations = excluded.accept_escalations",
+                        author_id,
+                        enabled)
+                        .execute(db).await?;
+
+                    msg.reply(ctx, format!("Escalations from other users: {}", enabled.emoji())).await?;
+                },
+                Actions::List => {
+                    let settings = {
+                        get_db!(ctx, db);
+                        crate::db::get_settings(db, author_id).await?
+                    };
+
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Settings")
+                                .field("gaps", settings.gap_notices.emoji(), true)
+                                .field("deleted", format!("`{}`", settings.on_delete.as_str()), true)
+                                .field("raids", match settings.raid_follow_minutes {
+                                    0 => false.emoji(),
+                                    minutes => format!("{} min", minutes),
+                                }, true)
+                                .field("followup", match settings.followup_secs {
+                                    0 => false.emoji(),
+                                    seconds => format!("{} s", seconds),
+                                }, true)
+                                .field("context", match (settings.context_lines, settings.context_delay_secs) {
+                                    (0, _) => false.emoji(),
+                                    (lines, 0) => format!("{} lines", lines),
+                                    (lines, delay) => format!("{} lines, {} s", lines, delay),
+                                }, true)
+                                .field("cooldown", match settings.cooldown_secs {
+                                    0 => false.emoji(),
+                                    seconds => format!("{} s", seconds),
+                                }, true)
+                                .field("trigger-cooldown", match settings.trigger_cooldown_secs {
+                                    0 => false.emoji(),
+                                    seconds => format!("{} s", seconds),
+                                }, true)
+                                .field("duplicates", match settings.duplicate_secs {
+                                    0 => false.emoji(),
+                                    seconds => format!("{} s", seconds),
+                                }, true)
+                                .field("coalesce", match settings.coalesce_secs {
+                                    0 => false.emoji(),
+                                    seconds => format!("{} s", seconds),
+                                }, true)
+                                .field("timezone", format!("`{}`", settings.timezone), true)
+                                .field("quiet", format!("`{}`", settings.quiet_action.as_str()), true)
+                                .field("escalations", settings.accept_escalations.emoji(), true)
+                                .field("delivery", match settings.delivery_mode {
+                                    DeliveryMode::Instant => "`instant`".to_string(),
+                                    mode => format!("`{}`, {} min", mode.as_str(), settings.digest_minutes),
+                                }, true)
+                        )
+                    ).await?;
+                },
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::fmt::Write as _; // import without risk of name clashing
+use chrono::{NaiveTime, TimeZone};
+use serenity::prelude::*;
+use serenity::model::prelude::*;
+use serenity::framework::standard::CommandResult;
+use serenity::framework::standard::macros::{command, group};
+
+use clap::{Parser, Subcommand};
+
+use crate::discord::{CommandPrefix, DbConnection};
+use crate::discord::com::{get_bot_prefix, get_db};
+use crate::discord::quiet::timezone;
+use crate::styled_str;
+use crate::styled_str::escape_twitch_channel;
+
+/// Arguments to the snooze command
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// What to snooze
+    #[command(subcommand)]
+    target: Targets,
+}
+
+#[derive(Subcommand, Debug)]
+enum Targets {
+    /// Snooze one of your monitored channels (`#channel` works too)
+    Channel {
+        channel: String,
+
+        /// A duration like `2h` or `1h30m`, or `until HH:MM` in your timezone
+        #[arg(required = true)]
+        when: Vec<String>,
+    },
+    /// Snooze a trigger
+    Trigger {
+        /// ID of the trigger
+        id: i64,
+
+        /// A duration like `2h` or `1h30m`, or `until HH:MM` in your timezone
+        #[arg(required = true)]
+        when: Vec<String>,
+    },
+    /// Snooze everything
+    All {
+        /// A duration like `2h` or `1h30m`, or `until HH:MM` in your timezone
+        #[arg(required = true)]
+        when: Vec<String>,
+    },
+    /// List all snoozes
+    List,
+}
+
+/// Arguments to the unsnooze command
+#[derive(clap::Parser, Debug)]
+struct UnsnoozeArgs {
+    /// What to wake up
+    #[command(subcommand)]
+    target: UnsnoozeTargets,
+}
+
+#[derive(Subcommand, Debug)]
+enum UnsnoozeTargets {
+    /// Wake up a channel (`#channel` works too)
+    Channel {
+        channel: String,
+    },
+    /// Wake up a trigger
+    Trigger {
+        /// ID of the trigger
+        id: i64,
+    },
+    /// Remove every snooze
+    All,
+}
+
+/// Arguments after the command name, `#channel` as a shorthand for `channel <channel>`
+fn split_args(content: &str, prefix: &str) -> Vec<String> {
+    let mut args = content.trim_start_matches(prefix)
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if args.get(1).is_some_and(|arg| arg.starts_with('#')) {
+        args.insert(1, "channel".to_string());
+    }
+    args
+}
+
+const EXPECTED_WHEN: &str = "Expected a duration like `2h` or `1h30m`, or `until HH:MM`";
+/// Snoozes end within a year at the latest
+const MAX_SNOOZE_SECS: i64 = 365 * 24 * 60 * 60;
+
+/// When a snooze given as a duration or `until HH:MM` (in `tz`) ends, or the reply explaining why it can't
+fn parse_until(when: &[String], tz: chrono_tz::Tz) -> Result<i64, String> {
+    let now = chrono::Utc::now();
+    match when {
+        [until, time] if until == "until" => {
+            let time = NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| EXPECTED_WHEN.to_string())?;
+            let local_now = now.with_timezone(&tz);
+            // Today if it's still ahead, tomorrow otherwise
+            let mut date = local_now.date_naive();
+            if local_now.time() >= time {
+                date = date.succ_opt().ok_or_else(|| EXPECTED_WHEN.to_string())?;
+            }
+            // Skipped by a daylight saving change
+            tz.from_local_datetime(&date.and_time(time)).earliest()
+                .map(|until| until.timestamp())
+                .ok_or_else(|| format!("{} doesn't exist on {} in your timezone ({}), the clocks skip it", time.format("%H:%M"), date, tz))
+        }
+        [duration] => {
+            let duration = styled_str::parse_duration(duration).ok_or_else(|| EXPECTED_WHEN.to_string())?;
+            if duration > MAX_SNOOZE_SECS {
+                return Err("A snooze can't be longer than a year".to_string());
+            }
+            now.timestamp().checked_add(duration).ok_or_else(|| EXPECTED_WHEN.to_string())
+        }
+        _ => Err(EXPECTED_WHEN.to_string()),
+    }
+}
+
+#[group]
+#[commands(snooze, unsnooze)]
+struct Snooze;
+
+#[command]
+async fn snooze(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = Args::try_parse_from(split_args(&msg.content, &prefix));
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            get_db!(ctx, db);
+            let settings = crate::db::get_settings(db, author_id).await?;
+            let tz = timezone(&settings.timezone);
+
+            let (channel, trigger, when, what) = match args.target {
+                Targets::Channel { channel, when } => {
+                    let channel = channel.trim_start_matches('#').to_lowercase();
+                    let watched = sqlx::query!("SELECT EXISTS(SELECT 1 FROM watched WHERE discord_user_id = ? AND channel = ?) AS result",
+                        author_id,
+                        channel)
+                        .fetch_one(&mut *db).await?
+                        .result == 1;
+                    if !watched {
+                        msg.reply(ctx, format!("Channel {} is not in your list", escape_twitch_channel(&channel))).await?;
+                        return Ok(());
+                    }
+                    let what = format!("#{}", escape_twitch_channel(&channel));
+                    (Some(channel), None, when, what)
+                },
+                Targets::Trigger { id, when } => {
+                    let Some(trigger_id) = crate::db::trigger_id(db, author_id, id).await? else {
+                        msg.reply(ctx, format!("No trigger with ID **{}**", id)).await?;
+                        return Ok(());
+                    };
+                    (None, Some(trigger_id), when, format!("Trigger **ID {}**", id))
+                },
+                Targets::All { when } => (None, None, when, "Everything".to_string()),
+                Targets::List => {
+                    let rows = sqlx::query!("SELECT snoozes.channel, snoozes.until, triggers.trigger AS \"trigger?\" FROM snoozes
+                            LEFT JOIN triggers ON triggers.id = snoozes.trigger_id
+                            WHERE snoozes.discord_user_id = ? ORDER BY snoozes.until",
+                        author_id)
+                        .fetch_all(db)
+                        .await?;
+
+                    let mut reply = String::new();
+                    for row in &rows {
+                        let what = match (&row.channel, &row.trigger) {
+                            (Some(channel), _) => format!("#{}", escape_twitch_channel(channel)),
+                            (None, Some(trigger)) => format!("`{}`", trigger),
+                            (None, None) => "Everything".to_string(),
+                        };
+                        let _ = writeln!(reply, "{} until <t:{}:f>", what, row.until);
+                    }
+                    if rows.is_empty() {
+                        reply.push_str("Nothing snoozed");
+                    }
+                    msg.channel_id.send_message(ctx, |m|
+                        m.embed(|e|
+                            e.title("Snoozed")
+                                .description(reply)
+                        )
+                    ).await?;
+                    return Ok(());
+                },
+            };
+
+            let until = match parse_until(&when, tz) {
+                Ok(until) => until,
+                Err(reply) => {
+                    msg.reply(ctx, reply).await?;
+                    return Ok(());
+                }
+            };
+
+            crate::db::snooze(db, author_id, channel.as_deref(), trigger, until).await?;
+            msg.reply(ctx, format!("{} snoozed until <t:{}:f>", what, until)).await?;
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
+
+#[command]
+async fn unsnooze(ctx: &Context, msg: &Message) -> CommandResult {
+    let prefix = get_bot_prefix!(ctx);
+
+    let args = UnsnoozeArgs::try_parse_from(split_args(&msg.content, &prefix));
+
+    let author_id = msg.author.id.0 as i64;
+
+    match args {
+        Ok(args) => {
+            get_db!(ctx, db);
+
+            let res = match args.target {
+                UnsnoozeTargets::Channel { channel } => {
+                    let channel = channel.trim_start_matches('#').to_lowercase();
+                    sqlx::query!("DELETE FROM snoozes WHERE discord_user_id = ? AND channel = ?",
+                        author_id,
+                        channel)
+                        .execute(&mut *db).await?
+                },
+                UnsnoozeTargets::Trigger { id } => {
+                    let Some(trigger_id) = crate::db::trigger_id(db, author_id, id).await? else {
+                        msg.reply(ctx, format!("No trigger with ID **{}**", id)).await?;
+                        return Ok(());
+                    };
+                    sqlx::query!("DELETE FROM snoozes WHERE discord_user_id = ? AND trigger_id = ?",
+                        author_id,
+                        trigger_id)
+                        .execute(&mut *db).await?
+                },
+                UnsnoozeTargets::All => {
+                    sqlx::query!("DELETE FROM snoozes WHERE discord_user_id = ?",
+                        author_id)
+                        .execute(&mut *db).await?
+                },
+            };
+            if res.rows_affected() == 0 {
+                msg.reply(ctx, "Nothing to unsnooze").await?;
+            } else {
+                msg.reply(ctx, format!("Removed {} snoozes", res.rows_affected())).await?;
+            }
+        },
+        Err(e) => {
+            msg.reply(ctx, styled_str::fmt_args_error(&e)).await?;
+        },
+    }
+
+    Ok(())
+}
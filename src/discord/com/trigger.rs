@@ -41,16 +41,74 @@ enum Actions {
         /// Use regex pattern matching (regex)
         #[arg(short, long, default_value_t = false)]
         regex: bool,
+
+        /// Only notify once this many messages match within the window
+        #[arg(short, long, value_parser = clap::value_parser!(u16).range(2..=1000))]
+        burst: Option<u16>,
+
+        /// Burst window in seconds
+        #[arg(short, long, default_value_t = 30, requires = "burst", value_parser = clap::value_parser!(u16).range(1..=3600))]
+        window: u16,
+
+        /// Count each chatter only once in a burst
+        #[arg(short, long, default_value_t = false, requires = "burst")]
+        distinct: bool,
+
+        /// Urgent: re-ping every this many minutes until acknowledged.
+        /// Quiet hours still apply: matches go to the digest (or are dropped) and re-pings wait until they are over
+        #[arg(short, long, conflicts_with = "burst", value_parser = clap::value_parser!(u16).range(1..=120))]
+        urgent: Option<u16>,
+
+        /// Also ping this Discord user (mention or ID) when an urgent DM goes unacknowledged,
+        /// they have to accept escalations first (`settings escalations on`)
+        #[arg(short, long, requires = "urgent", value_parser = parse_user)]
+        escalate: Option<u64>,
     },
     /// Remove triggers from the list of triggers
     Remove {
         /// IDs of the triggers to remove
         ids: Vec<i64>,
     },
+    /// Pause triggers without removing them
+    Disable {
+        /// IDs of the triggers to pause
+        #[arg(required = true)]
+        ids: Vec<i64>,
+    },
+    /// Resume paused triggers
+    Enable {
+        /// IDs of the triggers to resume
+        #[arg(required = true)]
+        ids: Vec<i64>,
+    },
     /// List all triggers
     List,
 }
 
+fn parse_user(s: &str) -> Result<u64, String> {
+    serenity::utils::parse_username(s)
+        .or_else(|| s.parse().ok())
+        .ok_or_else(|| "expected a user mention or ID".to_string())
+}
+
+/// Pause or resume triggers with user-facing `ids`, all or none
+async fn set_disabled(ctx: &Context, msg: &Message, author_id: i64, ids: &[i64], disabled: bool) -> CommandResult {
+    get_db!(ctx, db);
+
+    let mut tx = db.begin().await?;
+    for id in ids {
+        let Some(trigger_id) = crate::db::trigger_id(&mut tx, author_id, *id).await? else {
+            tx.rollback().await?;
+            msg.reply(ctx, format!("No trigger with ID **{}**. Rollback.", id)).await?;
+            return Ok(());
+        };
+        crate::db::set_trigger_disabled(&mut tx, author_id, trigger_id, disabled).await?;
+    }
+    tx.commit().await?;
+    msg.reply(ctx, format!("{} {} triggers", if disabled { "Disabled" } else { "Enabled" }, ids.len())).await?;
+    Ok(())
+}
+
 #[group]
 #[commands(trigger)]
 struct Trigger;
@@ -66,7 +124,7 @@ async fn trigger(ctx: &Context, msg: &Message) -> CommandResult {
     match args {
         Ok(args) => {
             match args.action {
-                Actions::Add { trigger, case_sensitive, regex } => {
+                Actions::Add { trigger, case_sensitive, regex, burst, window, distinct, urgent, escalate } => {
                     let trigger = match case_sensitive {
                         true => trigger,
                         false => trigger.to_lowercase(),
@@ -74,23 +132,42 @@ async fn trigger(ctx: &Context, msg: &Message) -> CommandResult {
 
                     get_db!(ctx, db);
 
+                    let burst_count = burst.unwrap_or(0);
+                    let burst_window = if burst.is_some() { window } else { 0 };
+                    let urgent_minutes = urgent.unwrap_or(0);
+                    let escalate_to = escalate.map(|id| id as i64);
+
+                    // Nobody gets pinged with someone else's alerts without agreeing to it
+                    if let Some(escalate_to) = escalate_to.filter(|id| *id != author_id) {
+                        let accepts = sqlx::query!("SELECT EXISTS(SELECT 1 FROM settings WHERE discord_user_id = ? AND accept_escalations) AS result",
+                            escalate_to)
+                            .fetch_one(&mut *db).await?
+                            .result == 1;
+                        if !accepts {
+                            msg.reply(ctx, "That user doesn't accept escalations, they can turn them on with `settings escalations on`").await?;
+                            return Ok(());
+                        }
+                    }
+
                     let mut tx = db.begin().await?;
-                    let res = sqlx::query!("INSERT INTO triggers (discord_user_id, trigger, case_sensitive, regex) VALUES (?, ?, ?, ?)",
+                    let res = sqlx::query!("INSERT INTO triggers (discord_user_id, trigger, case_sensitive, regex, burst_count, burst_window, burst_distinct, urgent_minutes, escalate_to) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
                         author_id,
                         trigger,
                         case_sensitive,
-                        regex)
+                        regex,
+                        burst_count,
+                        burst_window,
+                        distinct,
+                        urgent_minutes,
+                        escalate_to)
                         .execute(&mut tx)
                         .await;
                     if let Err(e) = res {
                         tx.rollback().await?;
                         match e {
-                            sqlx::Error::Database(e) => {
-                                if e.code() == Some(Cow::Borrowed("2067")) { // SQLITE_CONSTRAINT_UNIQUE (UNIQUE constraint failed)
-                                    msg.reply(ctx, "Trigger already exists").await?;
-                                } else {
-                                    msg.reply(ctx, "Failed to add trigger").await?;
-                                }
+                            // SQLITE_CONSTRAINT_UNIQUE (UNIQUE constraint failed)
+                            sqlx::Error::Database(e) if e.code() == Some(Cow::Borrowed("2067")) => {
+                                msg.reply(ctx, "Trigger already exists").await?;
                             },
                             _ => {
                                 msg.reply(ctx, "Failed to add trigger").await?;
@@ -151,11 +228,21 @@ async fn trigger(ctx: &Context, msg: &Message) -> CommandResult {
                         msg.reply(ctx, format!("Failed to remove trigger: **{}**. Rollback.", failed_ids[0])).await?;
                     }
                 },
+                Actions::Disable { ids } => set_disabled(ctx, msg, author_id, &ids, true).await?,
+                Actions::Enable { ids } => set_disabled(ctx, msg, author_id, &ids, false).await?,
                 Actions::List => {
                     get_db!(ctx, db);
 
+                    let now = chrono::Utc::now().timestamp();
+                    let snoozes = sqlx::query!("SELECT trigger_id, until FROM snoozes WHERE discord_user_id = ? AND channel IS NULL AND until > ?",
+                        author_id,
+                        now)
+                        .fetch_all(&mut *db)
+                        .await?;
+                    let snoozed_all = snoozes.iter().find(|row| row.trigger_id.is_none()).map(|row| row.until);
+
                     let res = sqlx::query_as!(crate::db::TriggerRecordNoDiscord,
-                        "SELECT id, trigger, case_sensitive, regex FROM triggers WHERE discord_user_id = ?",
+                        "SELECT id, trigger, case_sensitive, regex, burst_count, burst_window, burst_distinct, urgent_minutes, escalate_to, disabled FROM triggers WHERE discord_user_id = ?",
                         author_id)
                         .fetch_all(db)
                         .await;
@@ -164,20 +251,39 @@ async fn trigger(ctx: &Context, msg: &Message) -> CommandResult {
                         return Ok(());
                     }
                     let mut res = res.unwrap();
-                    res.sort_by(|a, b| a.id.cmp(&b.id));
+                    res.sort_by_key(|a| a.id);
                     let mut reply = String::new();
-                    let mut i = 1;
-                    for row in res {
+                    for (i, row) in (1..).zip(res) {
                         use crate::discord::extra::IntoEmoji;
-                        let _ = writeln!(reply, "**ID {}**: `{}` (case_sensitive: {}, regex: {})",
+                        let _ = write!(reply, "**ID {}**: `{}` (case_sensitive: {}, regex: {})",
                                  i, row.trigger, row.case_sensitive.emoji(), row.regex.emoji());
-                        i += 1;
+                        if row.burst_count > 0 {
+                            let _ = write!(reply, " burst: {}{} in {}s",
+                                 row.burst_count, if row.burst_distinct { " chatters" } else { "" }, row.burst_window);
+                        }
+                        if row.urgent_minutes > 0 {
+                            let _ = write!(reply, " 🚨 urgent: every {} min", row.urgent_minutes);
+                            if let Some(escalate_to) = row.escalate_to {
+                                let _ = write!(reply, ", escalating to {}", UserId(escalate_to as u64).mention());
+                            }
+                        }
+                        if row.disabled {
+                            reply.push_str(" ⏸ disabled");
+                        }
+                        if let Some(snooze) = snoozes.iter().find(|snooze| snooze.trigger_id == Some(row.id)) {
+                            let _ = write!(reply, " 💤 until <t:{}:t>", snooze.until);
+                        }
+                        reply.push('\n');
                     }
                     msg.channel_id.send_message(ctx, |m|
-                        m.embed(|e|
+                        m.embed(|e| {
                             e.title("Triggers")
-                            .description(reply)
-                        )
+                                .description(reply);
+                            if let Some(until) = snoozed_all {
+                                e.field("💤", format!("Everything snoozed until <t:{}:f>", until), false);
+                            }
+                            e
+                        })
                     ).await?;
                 },
             }
@@ -0,0 +1,120 @@
+use std::time::Duration;
+use chrono::TimeZone;
+use tracing::{info, error};
+
+use crate::discord::outbox::{Outbox, OutboxError};
+use crate::discord::{quiet, TriggerEvent};
+use crate::twitch::TwitchMessageSimple;
+
+
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+/// Highlighted lines shown per channel, the rest is only counted
+const TOP_LINES: usize = 5;
+
+/// Matches of one channel in a digest
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DigestChannel {
+    pub channel: String,
+    pub count: usize,
+    /// `(author, highlighted message)`, the lines with the most matches first
+    pub top: Vec<(String, String)>,
+}
+
+/// Keep a match for the next digest, `held` if it came in during quiet hours
+pub async fn store(con: &mut sqlx::SqliteConnection, discord_user_id: i64, message: &TwitchMessageSimple, held: bool) -> Result<(), sqlx::Error> {
+    let highlighted = message.message_highlighted("**");
+    let matches = message.triggers.len() as i64;
+    let now = chrono::Utc::now().timestamp();
+    // Without tags there is no id, only bans and timeouts can remove the item then
+    let twitch_message_id = Some(message.id.as_str()).filter(|id| !id.is_empty());
+    sqlx::query!("INSERT INTO digest_items (discord_user_id, channel, author, message, matches, held, created_at, twitch_message_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        discord_user_id,
+        message.channel,
+        message.author,
+        highlighted,
+        matches,
+        held,
+        now,
+        twitch_message_id)
+        .execute(con).await?;
+    Ok(())
+}
+
+/// Send the digests that are due, items wait in the `digest_items` table until then
+pub async fn run(pool: sqlx::SqlitePool, outbox: Outbox) {
+    let mut interval = tokio::time::interval(CHECK_EVERY);
+    loop {
+        interval.tick().await;
+        if let Err(e) = send_due(&pool, &outbox).await {
+            error!("[DS] Error sending digests: {}", e);
+        }
+    }
+}
+
+/// A digest is due once its oldest item waited the user's interval,
+/// or right when quiet hours are over if something was held during them
+async fn send_due(pool: &sqlx::SqlitePool, outbox: &Outbox) -> Result<(), OutboxError> {
+    let now = chrono::Utc::now().timestamp();
+    let pending = sqlx::query!(
+        r#"SELECT digest_items.discord_user_id, MIN(digest_items.created_at) AS "since!: i64", MAX(digest_items.held) AS "held!: bool",
+                COALESCE(MAX(settings.digest_minutes), 60) AS "digest_minutes!: i64" FROM digest_items
+            LEFT JOIN settings ON settings.discord_user_id = digest_items.discord_user_id
+            GROUP BY digest_items.discord_user_id"#)
+        .fetch_all(pool).await?;
+
+    let mut con = pool.acquire().await?;
+    for row in pending {
+        if !row.held && row.since > now - row.digest_minutes * 60 {
+            continue;
+        }
+        if quiet::quiet_now(&mut con, row.discord_user_id).await?.is_some() {
+            continue;
+        }
+
+        let items = sqlx::query!("SELECT id, channel, author, message, matches FROM digest_items WHERE discord_user_id = ? ORDER BY channel, id",
+            row.discord_user_id)
+            .fetch_all(pool).await?;
+        let Some(last_id) = items.iter().map(|item| item.id).max() else {
+            continue;
+        };
+
+        let mut channels: Vec<DigestChannel> = Vec::new();
+        let mut lines: Vec<(i64, String, String)> = Vec::new();
+        for item in items {
+            if channels.last().map(|channel| &channel.channel) != Some(&item.channel) {
+                if let Some(channel) = channels.last_mut() {
+                    channel.top = top_lines(&mut lines);
+                }
+                channels.push(DigestChannel { channel: item.channel.clone(), count: 0, top: Vec::new() });
+            }
+            channels.last_mut().unwrap().count += 1;
+            lines.push((item.matches, item.author, item.message));
+        }
+        if let Some(channel) = channels.last_mut() {
+            channel.top = top_lines(&mut lines);
+        }
+        // Busiest channels first
+        channels.sort_by_key(|channel| std::cmp::Reverse(channel.count));
+
+        info!("[DS] Sending digest of {} channels to {}", channels.len(), row.discord_user_id);
+        outbox.push(TriggerEvent::Digest {
+            receiver: row.discord_user_id as u64,
+            channels,
+            since: chrono::Utc.timestamp_opt(row.since, 0).single().unwrap_or_else(chrono::Utc::now),
+            timestamp: chrono::Utc::now(),
+        }).await?;
+        sqlx::query!("DELETE FROM digest_items WHERE discord_user_id = ? AND id <= ?", row.discord_user_id, last_id)
+            .execute(pool).await?;
+    }
+    Ok(())
+}
+
+/// Take the lines of a channel, keep the ones with the most matches (stable, so earlier lines win ties)
+fn top_lines(lines: &mut Vec<(i64, String, String)>) -> Vec<(String, String)> {
+    let mut lines = std::mem::take(lines);
+    lines.sort_by_key(|line| std::cmp::Reverse(line.0));
+    lines.into_iter()
+        .take(TOP_LINES)
+        .map(|(_, author, message)| (author, message))
+        .collect()
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::watch;
+use tracing::{info, error};
+
+use crate::discord::outbox::{Outbox, OutboxError};
+use crate::discord::TriggerEvent;
+use crate::twitch::ConnectionState;
+
+
+/// Shorter gaps (like a Twitch `RECONNECT`) are recorded but nobody is notified
+const MIN_NOTIFY_SECS: i64 = 30;
+
+pub fn fmt_timestamp(secs: i64, format: &str) -> String {
+    Utc.timestamp_opt(secs, 0).single()
+        .map(|t| t.format(format).to_string())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+/// Format a gap as `14:02–14:05 UTC`, with dates when it is not from today
+pub fn fmt_gap(started_at: i64, ended_at: Option<i64>) -> String {
+    let today = Utc::now().date_naive();
+    let format = match Utc.timestamp_opt(started_at, 0).single() {
+        Some(started) if started.date_naive() == today => "%H:%M",
+        _ => "%Y-%m-%d %H:%M",
+    };
+    match ended_at {
+        Some(ended_at) => format!("{}–{} UTC", fmt_timestamp(started_at, format), fmt_timestamp(ended_at, format)),
+        None => format!("{}–now UTC (ongoing)", fmt_timestamp(started_at, format)),
+    }
+}
+
+/// Record every time IRC was down and tell opted-in users once it is back
+pub async fn watch(pool: sqlx::SqlitePool, mut state: watch::Receiver<ConnectionState>, outbox: Outbox) {
+    // A gap still open from before a restart lasted until now, the bot wasn't watching either
+    let startup = Utc::now().timestamp();
+    if let Err(e) = sqlx::query!("UPDATE gaps SET ended_at = ? WHERE ended_at IS NULL", startup)
+        .execute(&pool).await {
+        error!("[DS] Error closing monitoring gaps: {}", e);
+    }
+
+    // The very first connect is not a gap
+    let mut ongoing: Option<(i64, DateTime<Utc>)> = None;
+    let mut was_connected = false;
+
+    while state.changed().await.is_ok() {
+        let connected = *state.borrow_and_update() == ConnectionState::Connected;
+        if connected == was_connected {
+            continue;
+        }
+        was_connected = connected;
+
+        let now = Utc::now();
+        if !connected {
+            let started_at = now.timestamp();
+            match sqlx::query!("INSERT INTO gaps (started_at) VALUES (?)", started_at)
+                .execute(&pool).await {
+                Ok(res) => { ongoing = Some((res.last_insert_rowid(), now)); }
+                Err(e) => { error!("[DS] Error recording monitoring gap: {}", e); }
+            }
+            continue;
+        }
+
+        let Some((id, started)) = ongoing.take() else { continue };
+        let ended_at = now.timestamp();
+        if let Err(e) = sqlx::query!("UPDATE gaps SET ended_at = ? WHERE id = ?", ended_at, id)
+            .execute(&pool).await {
+            error!("[DS] Error recording monitoring gap: {}", e);
+        }
+        info!("Monitoring gap: {}", fmt_gap(started.timestamp(), Some(ended_at)));
+
+        if (now - started).num_seconds() >= MIN_NOTIFY_SECS {
+            if let Err(e) = notify_users(&pool, &outbox, started.timestamp(), ended_at).await {
+                error!("[DS] Error notifying users about a monitoring gap: {}", e);
+            }
+        }
+    }
+}
+
+/// Queue gap notices like any other DM, so quiet hours and undeliverable users are respected
+async fn notify_users(pool: &sqlx::SqlitePool, outbox: &Outbox, started_at: i64, ended_at: i64) -> Result<(), OutboxError> {
+    let rows = sqlx::query!(
+        r#"SELECT settings.discord_user_id AS "discord_user_id!", COUNT(channels.id) AS "count!: i64"
+            FROM settings INNER JOIN channels ON channels.discord_user_id = settings.discord_user_id
+            WHERE settings.gap_notices = TRUE
+                AND settings.discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)
+            GROUP BY settings.discord_user_id"#)
+        .fetch_all(pool).await?;
+
+    for row in rows {
+        outbox.push(TriggerEvent::Gap {
+            receiver: row.discord_user_id as u64,
+            started_at,
+            ended_at,
+            channels: row.count,
+        }).await?;
+    }
+    Ok(())
+}
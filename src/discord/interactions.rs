@@ -0,0 +1,141 @@
+use serenity::builder::CreateComponents;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::prelude::*;
+use thiserror::Error;
+use tracing::info;
+
+use crate::discord::alerts;
+use crate::styled_str::escape_twitch_channel;
+use crate::twitch::TwitchMessageSimple;
+
+
+const IGNORE_PREFIX: &str = "ignore:";
+const SNOOZE_PREFIX: &str = "snooze:";
+const DISABLE_PREFIX: &str = "disable:";
+/// How long the Snooze button mutes a channel
+const SNOOZE_SECS: i64 = 60 * 60;
+/// Trigger IDs kept in the Disable button, a `custom_id` holds at most 100 characters
+const MAX_DISABLE_IDS: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum InteractionError {
+    #[error("SQL error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("Discord error: {0}")]
+    SerenityError(#[from] serenity::Error),
+}
+
+/// Buttons under a trigger notification, `coalesced` DMs collect several authors and triggers
+/// so they only get the channel ones
+pub fn notification_components<'a>(components: &'a mut CreateComponents, message: &TwitchMessageSimple, coalesced: bool) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        if !coalesced {
+            row.create_button(|b|
+                b.custom_id(format!("{}{}", IGNORE_PREFIX, message.author))
+                    .label(format!("Ignore {}", message.author))
+                    .style(ButtonStyle::Secondary)
+            );
+        }
+        row.create_button(|b|
+            b.custom_id(format!("{}{}", SNOOZE_PREFIX, message.channel))
+                .label("Snooze channel 1h")
+                .style(ButtonStyle::Secondary)
+        );
+        if !coalesced && !message.trigger_ids.is_empty() {
+            let ids = message.trigger_ids.iter()
+                .take(MAX_DISABLE_IDS)
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            row.create_button(|b|
+                b.custom_id(format!("{}{}", DISABLE_PREFIX, ids.join(",")))
+                    .label(if ids.len() == 1 { "Disable trigger" } else { "Disable triggers" })
+                    .style(ButtonStyle::Danger)
+            );
+        }
+        row.create_button(|b|
+            b.url(format!("https://twitch.tv/{}", message.channel))
+                .label("Open channel")
+                .style(ButtonStyle::Link)
+        )
+    })
+}
+
+/// Buttons on notifications, the `custom_id` prefix says which one
+pub async fn handle_component(ctx: &Context, pool: &sqlx::SqlitePool, component: &MessageComponentInteraction) -> Result<(), InteractionError> {
+    let custom_id = component.data.custom_id.as_str();
+    let user_id = component.user.id.0 as i64;
+
+    if let Some(id) = custom_id.strip_prefix(alerts::ACK_PREFIX).and_then(|id| id.parse::<i64>().ok()) {
+        if alerts::acknowledge(pool, id, component.user.id).await? {
+            // Only the Acknowledge row goes, the urgent DM keeps its other buttons (re-pings have none)
+            let message = match component.message.components.len() {
+                0 | 1 => None,
+                _ => alerts::message(pool, id).await?,
+            };
+            component.create_interaction_response(ctx, |r|
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d|
+                        d.content(format!("✅ Acknowledged by {}", component.user.tag()))
+                            .components(|c| match &message {
+                                Some(message) => notification_components(c, message, false),
+                                None => c,
+                            })
+                    )
+            ).await?;
+        } else {
+            reply_ephemeral(ctx, component, "This alert is already closed").await?;
+        }
+        return Ok(());
+    }
+
+    if let Some(author) = custom_id.strip_prefix(IGNORE_PREFIX) {
+        // Same as `ignore add`
+        let author = author.to_lowercase();
+        let mut con = pool.acquire().await?;
+        crate::db::add_ignore(&mut con, user_id, &author).await?;
+        info!("[DS] {} ignored {} from a notification", user_id, author);
+        reply_ephemeral(ctx, component, &format!("Ignoring {} now, `ignore remove` to undo", escape_twitch_channel(&author))).await?;
+        return Ok(());
+    }
+
+    if let Some(channel) = custom_id.strip_prefix(SNOOZE_PREFIX) {
+        // Same as `snooze #channel 1h`
+        let until = chrono::Utc::now().timestamp() + SNOOZE_SECS;
+        let mut con = pool.acquire().await?;
+        crate::db::snooze(&mut con, user_id, Some(channel), None, until).await?;
+        info!("[DS] {} snoozed #{} from a notification", user_id, channel);
+        reply_ephemeral(ctx, component, &format!("#{} snoozed until <t:{}:f>, `unsnooze #{}` to undo",
+            escape_twitch_channel(channel), until, escape_twitch_channel(channel))).await?;
+        return Ok(());
+    }
+
+    if let Some(ids) = custom_id.strip_prefix(DISABLE_PREFIX) {
+        // Same as `trigger disable`, but with database IDs
+        let mut con = pool.acquire().await?;
+        let mut disabled = 0;
+        for id in ids.split(',').filter_map(|id| id.parse::<i64>().ok()) {
+            if crate::db::set_trigger_disabled(&mut con, user_id, id, true).await? {
+                disabled += 1;
+            }
+        }
+        if disabled == 0 {
+            reply_ephemeral(ctx, component, "This trigger doesn't exist anymore").await?;
+        } else {
+            info!("[DS] {} disabled {} triggers from a notification", user_id, disabled);
+            reply_ephemeral(ctx, component, "Trigger disabled, see `trigger list` and `trigger enable <ids>` to resume").await?;
+        }
+        return Ok(());
+    }
+
+    reply_ephemeral(ctx, component, "This button doesn't do anything anymore").await?;
+    Ok(())
+}
+
+async fn reply_ephemeral(ctx: &Context, component: &MessageComponentInteraction, content: &str) -> Result<(), serenity::Error> {
+    component.create_interaction_response(ctx, |r|
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| d.content(content).ephemeral(true))
+    ).await
+}
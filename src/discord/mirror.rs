@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use ahash::AHashMap;
+use serenity::CacheAndHttp;
+use serenity::model::prelude::*;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::styled_str::{escape_twitch_channel, escape_twitch_message};
+use crate::twitch::ChatLine;
+
+
+/// One message per mirror every tick stays well under Discord's 5 messages / 5 seconds per channel
+const FLUSH_EVERY: Duration = Duration::from_secs(2);
+/// Lines waiting per mirror, the oldest are skipped when chat is faster than the mirror
+const MAX_BACKLOG: usize = 200;
+/// Discord's limit on message content
+const MESSAGE_CHARS: usize = 2000;
+
+/// Chat line for a guild channel mirroring the Twitch channel
+#[derive(Debug)]
+pub struct MirrorLine {
+    pub discord_channel_id: u64,
+    pub line: ChatLine,
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    lines: VecDeque<String>,
+    skipped: usize,
+}
+
+impl Backlog {
+    fn push(&mut self, line: String) {
+        if self.lines.len() == MAX_BACKLOG {
+            self.lines.pop_front();
+            self.skipped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Take as many lines as fit in one message
+    fn next_message(&mut self) -> Option<String> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let mut content = String::new();
+        if self.skipped > 0 {
+            content = format!("*… {} lines skipped*\n", self.skipped);
+            self.skipped = 0;
+        }
+        while let Some(line) = self.lines.front() {
+            let line_chars = line.chars().count() + 1;
+            if content.chars().count() + line_chars > MESSAGE_CHARS {
+                if content.is_empty() {
+                    // A single line can't be longer than a Twitch message, but just in case
+                    let line = self.lines.pop_front().unwrap();
+                    content = line.chars().take(MESSAGE_CHARS - 1).collect();
+                }
+                break;
+            }
+            content.push_str(&self.lines.pop_front().unwrap());
+            content.push('\n');
+        }
+        Some(content)
+    }
+}
+
+/// Relay mirrored chat to the guild channels, batched into multi-line messages
+pub async fn run(mut rx: mpsc::Receiver<MirrorLine>, cache_and_http: Arc<CacheAndHttp>) {
+    let mut backlogs: AHashMap<u64, Backlog> = AHashMap::new();
+    let mut interval = tokio::time::interval(FLUSH_EVERY);
+
+    loop {
+        tokio::select! {
+            mirror_line = rx.recv() => {
+                let Some(MirrorLine { discord_channel_id, line }) = mirror_line else {
+                    return;
+                };
+                backlogs.entry(discord_channel_id).or_default()
+                    .push(format!("**{}**: {}", escape_twitch_channel(&line.author), escape_twitch_message(&line.message)));
+            }
+            _ = interval.tick() => {
+                for (discord_channel_id, backlog) in backlogs.iter_mut() {
+                    let Some(content) = backlog.next_message() else {
+                        continue;
+                    };
+                    let res = ChannelId(*discord_channel_id).send_message(&cache_and_http.http, |m|
+                        m.content(content)
+                            // Chat must not ping anyone in the guild
+                            .allowed_mentions(|am| am.empty_parse())
+                    ).await;
+                    if let Err(e) = res {
+                        error!("[DS] Error sending mirrored chat to {}: {}", discord_channel_id, e);
+                    }
+                }
+                backlogs.retain(|_, backlog| !backlog.lines.is_empty() || backlog.skipped > 0);
+            }
+        }
+    }
+}
@@ -1,8 +1,9 @@
 
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use std::env;
+use std::fmt::Write as _; // import without risk of name clashing
 use std::sync::Arc;
-use tracing::{info};
+use tracing::{info, error};
 
 use serenity::{async_trait, CacheAndHttp};
 use serenity::prelude::*;
@@ -10,31 +11,137 @@ use serenity::model::prelude::*;
 use serenity::model::id::UserId;
 use serenity::framework::standard::{StandardFramework};
 use serenity::http::CacheHttp;
+use serenity::builder::CreateEmbed;
+use serenity::utils::Colour;
+use serenity::model::application::interaction::Interaction;
 use crate::discord::com::{get_bot_prefix, update_channel_count};
 use crate::IrcMessageEvent;
 
-use crate::twitch::TwitchMessageSimple;
+use crate::styled_str::{escape_twitch_channel, escape_twitch_message, fmt_duration};
+use crate::twitch::chatlog::ChatLogSettings;
+use crate::twitch::{BurstSummary, ChannelEvent, ChatHistory, ChatLine, PresenceChange, TwitchMessageSimple};
 
 
 mod com;
 mod extra;
+pub mod alerts;
+pub mod digest;
+pub mod gaps;
+pub mod interactions;
+pub mod mirror;
+pub mod outbox;
+pub mod quiet;
+pub mod snooze;
+pub mod reconcile;
 
 
-#[derive(Debug)]
-pub struct TriggerEvent {
-    pub receiver: u64,
-    pub message: TwitchMessageSimple,
-    pub timestamp: chrono::DateTime<chrono::Utc>,
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum TriggerEvent {
+    /// Chat message matching some of the receiver's triggers
+    Message {
+        receiver: u64,
+        message: TwitchMessageSimple,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Next message of an author that triggered a notification, appended to it if possible
+    FollowUp {
+        receiver: u64,
+        channel: String,
+        author: String,
+        message: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Burst trigger fired, many matching messages in a short time
+    Burst {
+        receiver: u64,
+        summary: BurstSummary,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Subscribed event in one of the receiver's channels
+    Channel {
+        receiver: u64,
+        channel: String,
+        event: ChannelEvent,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Watched chatters joined or left the receiver's channels
+    Presence {
+        receiver: u64,
+        changes: Vec<PresenceChange>,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Summary of the matches collected since `since`, grouped by channel
+    Digest {
+        receiver: u64,
+        channels: Vec<digest::DigestChannel>,
+        since: chrono::DateTime<chrono::Utc>,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Urgent match still not acknowledged, sent again (to the escalation user if `escalated_from` is set)
+    Reping {
+        receiver: u64,
+        alert: alerts::Alert,
+        escalated_from: Option<u64>,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// A snooze of the receiver is over
+    SnoozeEnded {
+        receiver: u64,
+        /// Already formatted markdown
+        what: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Chat monitoring was down for a while, `channels` of the receiver's were affected
+    Gap {
+        receiver: u64,
+        started_at: i64,
+        ended_at: i64,
+        channels: i64,
+    },
+    /// Events dropped because the receiver's backlog was full
+    Overflow {
+        receiver: u64,
+        dropped: i64,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Moderators deleted the message behind an earlier DM, edit or remove it
+    Retract {
+        receiver: u64,
+        dm_channel_id: u64,
+        dm_message_id: u64,
+        channel: String,
+        author: String,
+        remove: bool,
+        /// Set for a line of a coalesced DM, only that line is retracted
+        #[serde(default)]
+        line: Option<String>,
+    },
 }
 
 impl TriggerEvent {
     pub fn new(receiver: u64, message: TwitchMessageSimple, timestamp: chrono::DateTime<chrono::Utc>) -> Self {
-        Self {
+        Self::Message {
             receiver,
             message,
             timestamp,
         }
     }
+
+    pub fn receiver(&self) -> u64 {
+        match self {
+            TriggerEvent::Message { receiver, .. }
+            | TriggerEvent::FollowUp { receiver, .. }
+            | TriggerEvent::Burst { receiver, .. }
+            | TriggerEvent::Channel { receiver, .. }
+            | TriggerEvent::Presence { receiver, .. }
+            | TriggerEvent::Digest { receiver, .. }
+            | TriggerEvent::Reping { receiver, .. }
+            | TriggerEvent::SnoozeEnded { receiver, .. }
+            | TriggerEvent::Gap { receiver, .. }
+            | TriggerEvent::Overflow { receiver, .. }
+            | TriggerEvent::Retract { receiver, .. } => *receiver,
+        }
+    }
 }
 
 macro_rules! make_type_key {
@@ -50,8 +157,21 @@ make_type_key!(ChannelCount, i32);
 make_type_key!(CommandPrefix, String);
 make_type_key!(DbConnection, Mutex<sqlx::pool::PoolConnection<sqlx::Sqlite>>);
 make_type_key!(IrcEventSender, tokio::sync::mpsc::Sender<IrcMessageEvent>);
+make_type_key!(RecentChat, ChatHistory);
+make_type_key!(ChatLogs, Option<ChatLogSettings>);
 
-struct Handler;
+pub fn make_activity(channel_count: i32, prefix: &str) -> Activity {
+    Activity::watching(format!("{} chats | DM {}help", channel_count, prefix))
+}
+
+pub fn owner_id() -> Option<UserId> {
+    let owner_id = env::var("DISCORD_OWNER_ID").unwrap_or_default();
+    owner_id.parse::<u64>().ok().map(UserId::from)
+}
+
+struct Handler {
+    pool: sqlx::SqlitePool,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
@@ -60,18 +180,38 @@ impl EventHandler for Handler {
 
         update_channel_count!(ctx, 0);
     }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::MessageComponent(component) = interaction {
+            if let Err(e) = interactions::handle_component(&ctx, &self.pool, &component).await {
+                error!("[DS] Error handling button `{}`: {}", component.data.custom_id, e);
+            }
+        }
+    }
+
+    async fn message(&self, _ctx: Context, msg: Message) {
+        if msg.author.bot || msg.guild_id.is_some() {
+            return;
+        }
+        if let Err(e) = outbox::resume_delivery(&self.pool, msg.author.id.0 as i64).await {
+            error!("[DS] Error resuming delivery: {}", e);
+        }
+    }
 }
 
-pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>, irc_tx: tokio::sync::mpsc::Sender<IrcMessageEvent>) -> Client {
+pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>,
+                         irc_tx: tokio::sync::mpsc::Sender<IrcMessageEvent>,
+                         history: ChatHistory,
+                         chat_logs: Option<ChatLogSettings>,
+                         pool: sqlx::SqlitePool) -> Client {
     let prefix = env::var("DISCORD_PREFIX").unwrap_or_else(|_| "frog!".to_string());
 
     // Configure discord bot
     let d_framework = StandardFramework::new()
         .configure(|c| {
             let mut owner_ids = HashSet::new();
-            let owner_id = env::var("DISCORD_OWNER_ID").unwrap_or_default();
-            if let Ok(owner_id_num) = owner_id.parse::<u64>() {
-                owner_ids.insert(UserId::from(owner_id_num));
+            if let Some(owner_id) = owner_id() {
+                owner_ids.insert(owner_id);
             }
 
             c.prefix(prefix.clone()).owners(owner_ids)
@@ -79,19 +219,28 @@ pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>, i
         .group(&com::GENERAL_GROUP)
         .group(&com::CHANNEL_GROUP)
         .group(&com::TRIGGER_GROUP)
-        .group(&com::IGNORE_GROUP);
+        .group(&com::IGNORE_GROUP)
+        .group(&com::SETTINGS_GROUP)
+        .group(&com::GAPS_GROUP)
+        .group(&com::EVENT_GROUP)
+        .group(&com::PRESENCE_GROUP)
+        .group(&com::QUIET_GROUP)
+        .group(&com::SNOOZE_GROUP)
+        .group(&com::RECENT_GROUP)
+        .group(&com::MIRROR_GROUP)
+        .group(&com::LOGS_GROUP);
 
     // Login discord bot
     let d_token = env::var("DISCORD_TOKEN").expect("token");
     let intents = GatewayIntents::non_privileged();
     let d_client = Client::builder(d_token, intents)
-        .event_handler(Handler)
+        .event_handler(Handler { pool })
         .framework(d_framework)
         .await
         .expect("Error creating client");
 
 
-    let channel_count = sqlx::query!("SELECT COUNT(DISTINCT channel) as count FROM channels")
+    let channel_count = sqlx::query!("SELECT COUNT(*) as count FROM joined_channels")
         .fetch_one(&mut db_con)
         .await
         .expect("Error counting channels in DB")
@@ -103,6 +252,8 @@ pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>, i
         data.insert::<CommandPrefix>(prefix.clone());
         data.insert::<DbConnection>(Mutex::new(db_con));
         data.insert::<IrcEventSender>(irc_tx);
+        data.insert::<RecentChat>(history);
+        data.insert::<ChatLogs>(chat_logs);
     }
 
     d_client
@@ -110,22 +261,507 @@ pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>, i
 
 
 
+/// How long sent DMs are remembered for retraction
+const NOTIFICATION_RETENTION_SECS: i64 = 24 * 60 * 60;
+/// Discord's limit on embed descriptions
+const EMBED_DESCRIPTION_MAX: usize = 4096;
+
 // Notify user of the trigger event
-pub async fn notify_user(cache_and_http: Arc<CacheAndHttp>, event: TriggerEvent) -> std::result::Result<(), serenity::Error> {
-    UserId::from(event.receiver)
-        .create_dm_channel(cache_and_http.clone()).await?
-        .send_message(cache_and_http.http(),|m|
-            m.embed(|e|
-                e.description(event.message.message_highlighted("**"))
-                    .author(|a|
-                        a.name(format!("{} ∙ #{}", event.message.author, event.message.channel))
-                            .url(format!("https://twitch.tv/{}", event.message.channel))
+pub async fn notify_user(cache_and_http: Arc<CacheAndHttp>, pool: &sqlx::SqlitePool, event: TriggerEvent) -> std::result::Result<(), serenity::Error> {
+    let receiver = event.receiver();
+    // Retractions edit an existing DM, everything else opens the DM channel
+    let dm_channel = || UserId::from(receiver).create_dm_channel(cache_and_http.clone());
+    match event {
+        TriggerEvent::Message { receiver, message, timestamp } => {
+            let dm_channel = dm_channel().await?;
+            // Urgent matches always get a DM of their own, with the Acknowledge button
+            let alert_id = match &message.urgent {
+                Some(urgent) => match alerts::open(pool, receiver, &message, urgent).await {
+                    Ok(id) => Some(id),
+                    Err(e) => {
+                        error!("[DS] Error opening alert: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let coalesce_secs = if alert_id.is_some() { 0 } else { coalesce_window(pool, receiver).await };
+            // Coalesced embeds collect several authors, so each line names its own
+            let live_line = (coalesce_secs > 0).then(|| describe_live_line(&message));
+            if let Some(line) = &live_line {
+                if let Some(sent) = append_live(&cache_and_http, pool, receiver, &message.channel, line, coalesce_secs, timestamp).await {
+                    if !message.id.is_empty() {
+                        remember_notification(pool, receiver, &sent, &message, Some(line)).await;
+                    }
+                    return Ok(());
+                }
+            }
+            let sent = dm_channel.send_message(cache_and_http.http(),|m| {
+                if alert_id.is_some() {
+                    m.content("🚨 **Urgent**");
+                }
+                m.components(|c| {
+                    if let Some(id) = alert_id {
+                        alerts::ack_components(c, id);
+                    }
+                    interactions::notification_components(c, &message, coalesce_secs > 0)
+                });
+                m.embed(|e| {
+                    if let Some(line) = &live_line {
+                        e.description(line)
+                            .author(|a|
+                                a.name(message.channels_display())
+                                    .url(format!("https://twitch.tv/{}", message.channel))
+                            );
+                    } else {
+                        e.description(describe_message(&message))
+                            .author(|a|
+                                a.name(format!("{} ∙ {}", message.author, message.channels_display()))
+                                    .url(format!("https://twitch.tv/{}", message.channel))
+                            );
+                    }
+                    e.timestamp(timestamp);
+                    // Coalesced lines carry their own notes, the footer would only fit the first one
+                    let notes = describe_notes(&message);
+                    if live_line.is_none() && !notes.is_empty() {
+                        e.footer(|f| f.text(notes.join(" ∙ ")));
+                    }
+                    e
+                })
+            }).await;
+            let sent = match sent {
+                Ok(sent) => sent,
+                Err(e) => {
+                    if let Some(id) = alert_id {
+                        alerts::discard(pool, id).await;
+                    }
+                    return Err(e);
+                }
+            };
+            if coalesce_secs > 0 {
+                remember_live(pool, receiver, &message.channel, &sent).await;
+            }
+            if !message.id.is_empty() {
+                remember_notification(pool, receiver, &sent, &message, live_line.as_deref()).await;
+            }
+        }
+        TriggerEvent::FollowUp { receiver, channel, author, message, timestamp } => {
+            let line = format!("> {}", escape_twitch_message(&message));
+            if !append_follow_up(&cache_and_http, pool, receiver, &channel, &author, &line).await {
+                dm_channel().await?.send_message(cache_and_http.http(),|m|
+                    m.embed(|e|
+                        e.description(line)
+                            .author(|a|
+                                a.name(format!("{} ∙ #{} (follow-up)", author, channel))
+                                    .url(format!("https://twitch.tv/{}", channel))
+                            )
+                            .timestamp(timestamp)
                     )
-                    // .footer(|f|
-                    //     f.text(format!("#{}", event.message.channel))
-                    // )
-                    .timestamp(event.timestamp)
-            )
-        ).await?;
+                ).await?;
+            }
+        }
+        TriggerEvent::Burst { summary, timestamp, .. } => {
+            let samples = summary.samples.iter()
+                .map(|(author, message)| format!("**{}**: {}", escape_twitch_channel(author), escape_twitch_message(message)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.title("Burst")
+                        .colour(Colour::DARK_ORANGE)
+                        .description(format!("`{}` matched {} {} within {}\n\n{}",
+                            summary.trigger,
+                            summary.count,
+                            if summary.distinct { "chatters" } else { "times" },
+                            fmt_duration(summary.window.as_secs() as i64),
+                            samples))
+                        .author(|a|
+                            a.name(format!("#{}", summary.channel))
+                                .url(format!("https://twitch.tv/{}", summary.channel))
+                        )
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Channel { channel, event, timestamp, .. } => {
+            let (title, colour, description) = describe_channel_event(&event);
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.title(title)
+                        .colour(colour)
+                        .description(description)
+                        .author(|a|
+                            a.name(format!("#{}", channel))
+                                .url(format!("https://twitch.tv/{}", channel))
+                        )
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Presence { changes, timestamp, .. } => {
+            let description = changes.iter()
+                .map(|change| format!("{} **{}** {} #{}",
+                    if change.joined { "🟢" } else { "🔴" },
+                    escape_twitch_channel(&change.username),
+                    if change.joined { "joined" } else { "left" },
+                    escape_twitch_channel(&change.channel)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.title("Presence")
+                        .description(description)
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Digest { channels, since, timestamp, .. } => {
+            let total = channels.iter().map(|channel| channel.count).sum::<usize>();
+            let mut description = String::new();
+            for channel in &channels {
+                let mut section = format!("**#{}** ∙ {} {}\n",
+                    escape_twitch_channel(&channel.channel),
+                    channel.count,
+                    if channel.count == 1 { "match" } else { "matches" });
+                for (author, message) in &channel.top {
+                    section.push_str(&format!("`{}` {}\n", author, message));
+                }
+                section.push('\n');
+                // Whole channels only, the counts in the title still cover the rest
+                if description.chars().count() + section.chars().count() > EMBED_DESCRIPTION_MAX {
+                    break;
+                }
+                description.push_str(&section);
+            }
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.title(format!("Digest ∙ {} {} in {} {}",
+                        total,
+                        if total == 1 { "match" } else { "matches" },
+                        channels.len(),
+                        if channels.len() == 1 { "channel" } else { "channels" }))
+                        .description(description)
+                        .footer(|f| f.text(format!("Since {} UTC", since.format("%Y-%m-%d %H:%M"))))
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Reping { alert, escalated_from, timestamp, .. } => {
+            let content = match escalated_from {
+                Some(user) => format!("🚨 **Urgent**, escalated from {} (nobody acknowledged it yet)", UserId(user).mention()),
+                None => format!("🚨 **Urgent**, still not acknowledged (reminder {})", alert.pings),
+            };
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.content(content)
+                    .embed(|e|
+                        e.description(escape_twitch_message(&alert.message))
+                            .author(|a|
+                                a.name(format!("{} ∙ #{}", alert.author, alert.channel))
+                                    .url(format!("https://twitch.tv/{}", alert.channel))
+                            )
+                            .timestamp(timestamp)
+                    )
+                    .components(|c| alerts::ack_components(c, alert.id))
+            ).await?;
+        }
+        TriggerEvent::SnoozeEnded { what, timestamp, .. } => {
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.description(format!("⏰ {} is not snoozed anymore", what))
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Gap { started_at, ended_at, channels, .. } => {
+            let text = format!("Monitoring gap: {}, {} of your channels affected", gaps::fmt_gap(started_at, Some(ended_at)), channels);
+            dm_channel().await?.say(cache_and_http.http(), text).await?;
+        }
+        TriggerEvent::Overflow { dropped, timestamp, .. } => {
+            dm_channel().await?.send_message(cache_and_http.http(),|m|
+                m.embed(|e|
+                    e.description(format!("*… and {} more {} that didn't fit in the queue*",
+                        dropped,
+                        if dropped == 1 { "notification" } else { "notifications" }))
+                        .timestamp(timestamp)
+                )
+            ).await?;
+        }
+        TriggerEvent::Retract { dm_channel_id, dm_message_id, channel, author, remove, line, .. } => {
+            let dm_channel = ChannelId::from(dm_channel_id);
+            if let Some(line) = line {
+                retract_line(&cache_and_http, dm_channel, dm_message_id, &line, &author, remove).await?;
+            } else if remove {
+                dm_channel.delete_message(cache_and_http.http(), dm_message_id).await?;
+            } else {
+                dm_channel.edit_message(cache_and_http.http(), dm_message_id, |m|
+                    m.embed(|e|
+                        e.description("*[deleted by moderators]*")
+                            .author(|a|
+                                a.name(format!("{} ∙ #{}", author, channel))
+                                    .url(format!("https://twitch.tv/{}", channel))
+                            )
+                    )
+                ).await?;
+            }
+        }
+    }
     Ok(())
 }
+
+/// Keep the Twitch -> Discord message mapping, so the DM can be retracted later
+///
+/// `line` is the message's line in a coalesced DM, so only that line is retracted.
+async fn remember_notification(pool: &sqlx::SqlitePool, receiver: u64, sent: &Message, message: &TwitchMessageSimple, line: Option<&str>) {
+    let receiver = receiver as i64;
+    let dm_channel_id = sent.channel_id.0 as i64;
+    let dm_message_id = sent.id.0 as i64;
+    let now = chrono::Utc::now().timestamp();
+    let expired = now - NOTIFICATION_RETENTION_SECS;
+
+    let res = sqlx::query!("INSERT INTO notifications (discord_user_id, dm_channel_id, dm_message_id, twitch_message_id, channel, author, created_at, line) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        receiver,
+        dm_channel_id,
+        dm_message_id,
+        message.id,
+        message.channel,
+        message.author,
+        now,
+        line)
+        .execute(pool).await;
+    let res = match res {
+        Ok(_) => sqlx::query!("DELETE FROM notifications WHERE created_at < ?", expired)
+            .execute(pool).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        error!("[DS] Error remembering notification: {}", e);
+    }
+}
+
+/// Append `line` to the latest DM about `author` in `channel`, false if there is none or it's full
+async fn append_follow_up(cache_and_http: &Arc<CacheAndHttp>, pool: &sqlx::SqlitePool, receiver: u64,
+                          channel: &str, author: &str, line: &str) -> bool {
+    let receiver = receiver as i64;
+    let row = sqlx::query!("SELECT dm_channel_id, dm_message_id FROM notifications WHERE discord_user_id = ? AND channel = ? AND author = ? ORDER BY id DESC LIMIT 1",
+        receiver,
+        channel,
+        author)
+        .fetch_optional(pool).await;
+    let row = match row {
+        Ok(Some(row)) => row,
+        Ok(None) => return false,
+        Err(e) => {
+            error!("[DS] Error fetching notification to follow up: {}", e);
+            return false;
+        }
+    };
+
+    let dm_channel = ChannelId::from(row.dm_channel_id as u64);
+    let Ok(sent) = dm_channel.message(cache_and_http.http(), row.dm_message_id as u64).await else {
+        return false;
+    };
+    let Some(embed) = sent.embeds.into_iter().next() else {
+        return false;
+    };
+    let description = format!("{}\n{}", embed.description.clone().unwrap_or_default(), line);
+    if description.chars().count() > EMBED_DESCRIPTION_MAX {
+        return false;
+    }
+
+    let mut embed = CreateEmbed::from(embed);
+    embed.description(description);
+    dm_channel.edit_message(cache_and_http.http(), row.dm_message_id as u64, |m| m.set_embed(embed))
+        .await
+        .is_ok()
+}
+
+/// Seconds a coalesced DM stays open for new matches, 0 when the user doesn't coalesce
+async fn coalesce_window(pool: &sqlx::SqlitePool, receiver: u64) -> i64 {
+    let settings = match pool.acquire().await {
+        Ok(mut con) => crate::db::get_settings(&mut con, receiver as i64).await,
+        Err(e) => Err(e),
+    };
+    match settings {
+        Ok(settings) => settings.coalesce_secs,
+        Err(e) => {
+            error!("[DS] Error fetching settings: {}", e);
+            0
+        }
+    }
+}
+
+/// Keep the DM new matches in `channel` are appended to
+async fn remember_live(pool: &sqlx::SqlitePool, receiver: u64, channel: &str, sent: &Message) {
+    let receiver = receiver as i64;
+    let dm_channel_id = sent.channel_id.0 as i64;
+    let dm_message_id = sent.id.0 as i64;
+    let now = chrono::Utc::now().timestamp();
+    let expired = now - NOTIFICATION_RETENTION_SECS;
+
+    let res = sqlx::query!("INSERT INTO live_notifications (discord_user_id, channel, dm_channel_id, dm_message_id, updated_at) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(discord_user_id, channel) DO UPDATE SET dm_channel_id = excluded.dm_channel_id, dm_message_id = excluded.dm_message_id, updated_at = excluded.updated_at",
+        receiver,
+        channel,
+        dm_channel_id,
+        dm_message_id,
+        now)
+        .execute(pool).await;
+    let res = match res {
+        Ok(_) => sqlx::query!("DELETE FROM live_notifications WHERE updated_at < ?", expired)
+            .execute(pool).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        error!("[DS] Error remembering live notification: {}", e);
+    }
+}
+
+/// Append `line` to the live DM of `channel`, returns the edited DM,
+/// none if there is none, it went quiet for `window` seconds or it's full
+async fn append_live(cache_and_http: &Arc<CacheAndHttp>, pool: &sqlx::SqlitePool, receiver: u64,
+                     channel: &str, line: &str, window: i64, timestamp: chrono::DateTime<chrono::Utc>) -> Option<Message> {
+    let receiver = receiver as i64;
+    let now = chrono::Utc::now().timestamp();
+    let quiet_since = now - window;
+    let row = sqlx::query!("SELECT dm_channel_id, dm_message_id FROM live_notifications WHERE discord_user_id = ? AND channel = ? AND updated_at >= ?",
+        receiver,
+        channel,
+        quiet_since)
+        .fetch_optional(pool).await;
+    let row = match row {
+        Ok(Some(row)) => row,
+        Ok(None) => return None,
+        Err(e) => {
+            error!("[DS] Error fetching live notification: {}", e);
+            return None;
+        }
+    };
+
+    let dm_channel = ChannelId::from(row.dm_channel_id as u64);
+    let sent = dm_channel.message(cache_and_http.http(), row.dm_message_id as u64).await.ok()?;
+    let embed = sent.embeds.into_iter().next()?;
+    let description = format!("{}\n{}", embed.description.clone().unwrap_or_default(), line);
+    if description.chars().count() > EMBED_DESCRIPTION_MAX {
+        return None;
+    }
+
+    let mut embed = CreateEmbed::from(embed);
+    embed.description(description).timestamp(timestamp);
+    let edited = dm_channel.edit_message(cache_and_http.http(), row.dm_message_id as u64, |m| m.set_embed(embed)).await.ok()?;
+    if let Err(e) = sqlx::query!("UPDATE live_notifications SET updated_at = ? WHERE discord_user_id = ? AND channel = ?",
+        now,
+        receiver,
+        channel)
+        .execute(pool).await {
+        error!("[DS] Error updating live notification: {}", e);
+    }
+    Some(edited)
+}
+
+/// Take the line of a deleted message out of a coalesced DM, the other lines stay
+///
+/// The line is replaced by a placeholder, or dropped with `remove` (the whole DM goes once it's empty).
+async fn retract_line(cache_and_http: &Arc<CacheAndHttp>, dm_channel: ChannelId, dm_message_id: u64,
+                      line: &str, author: &str, remove: bool) -> std::result::Result<(), serenity::Error> {
+    let sent = dm_channel.message(cache_and_http.http(), dm_message_id).await?;
+    let Some(embed) = sent.embeds.into_iter().next() else {
+        return Ok(());
+    };
+    let description = embed.description.clone().unwrap_or_default();
+    // Already retracted (or edited away)
+    let Some(start) = description.find(line) else {
+        return Ok(());
+    };
+    let placeholder = format!("**{}**: *[deleted by moderators]*", escape_twitch_channel(author));
+    let description = [
+        description[..start].trim_end_matches('\n'),
+        if remove { "" } else { placeholder.as_str() },
+        description[start + line.len()..].trim_start_matches('\n'),
+    ].into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if description.is_empty() {
+        return dm_channel.delete_message(cache_and_http.http(), dm_message_id).await;
+    }
+    let mut embed = CreateEmbed::from(embed);
+    embed.description(description);
+    dm_channel.edit_message(cache_and_http.http(), dm_message_id, |m| m.set_embed(embed)).await?;
+    Ok(())
+}
+
+/// A match as one entry of a coalesced DM, with its notes since there is no footer per line
+fn describe_live_line(message: &TwitchMessageSimple) -> String {
+    let mut line = if message.context_before.is_empty() && message.context_after.is_empty() {
+        format!("**{}**: {}", escape_twitch_channel(&message.author), message.message_highlighted("**"))
+    } else {
+        describe_message(message)
+    };
+    let notes = describe_notes(message);
+    if !notes.is_empty() {
+        let _ = write!(line, " *({})*", notes.join(" ∙ "));
+    }
+    line
+}
+
+/// Shared chat and flood control notes of a match
+fn describe_notes(message: &TwitchMessageSimple) -> Vec<String> {
+    let mut notes = Vec::new();
+    if !message.shared_with.is_empty() {
+        notes.push("Shared chat".to_string());
+    }
+    if message.suppressed > 0 {
+        notes.push(format!("{} suppressed since the last one", message.suppressed));
+    }
+    notes
+}
+
+/// The highlighted message, between its context lines if any
+fn describe_message(message: &TwitchMessageSimple) -> String {
+    let highlighted = message.message_highlighted("**");
+    if message.context_before.is_empty() && message.context_after.is_empty() {
+        return highlighted;
+    }
+
+    let fmt_line = |line: &ChatLine| format!("`{}` {}", line.author, escape_twitch_message(&line.message));
+    let trigger = format!("➤ **{}**: {}", escape_twitch_channel(&message.author), highlighted);
+    let mut before = message.context_before.iter().map(fmt_line).collect::<VecDeque<_>>();
+    let mut after = message.context_after.iter().map(fmt_line).collect::<Vec<_>>();
+
+    // Drop the farthest lines until it fits in the embed
+    loop {
+        let description = before.iter()
+            .chain(std::iter::once(&trigger))
+            .chain(after.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        if description.chars().count() <= EMBED_DESCRIPTION_MAX {
+            return description;
+        }
+        if before.len() > after.len() {
+            before.pop_front();
+        } else if after.pop().is_none() {
+            return highlighted;
+        }
+    }
+}
+
+fn describe_channel_event(event: &ChannelEvent) -> (&'static str, Colour, String) {
+    match event {
+        ChannelEvent::Raid { from, viewers } =>
+            ("Raid", Colour::PURPLE, format!("**{}** is raiding with {} viewers", escape_twitch_channel(from), viewers)),
+        ChannelEvent::GiftBomb { gifter, count } =>
+            ("Gift bomb", Colour::GOLD, format!("**{}** is gifting {} subs", escape_twitch_channel(gifter), count)),
+        ChannelEvent::Announcement { author, message } =>
+            ("Announcement", Colour::BLUE, format!("**{}**: {}", escape_twitch_channel(author), escape_twitch_message(message))),
+        ChannelEvent::Ban { username, duration: Some(duration) } =>
+            ("Timeout", Colour::ORANGE, format!("**{}** was timed out for {}", escape_twitch_channel(username), fmt_duration(*duration as i64))),
+        ChannelEvent::Ban { username, duration: None } =>
+            ("Ban", Colour::RED, format!("**{}** was banned", escape_twitch_channel(username))),
+        ChannelEvent::ModeChange { mode, enabled } =>
+            ("Chat mode", Colour::LIGHT_GREY, format!("{} mode is now **{}**", mode.name(), if *enabled { "on" } else { "off" })),
+    }
+}
+
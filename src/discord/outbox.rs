@@ -0,0 +1,307 @@
+use std::sync::Arc;
+use std::time::Duration;
+use ahash::AHashMap;
+use futures_util::StreamExt;
+use rand::Rng;
+use serenity::CacheAndHttp;
+use serenity::http::error::Error as HttpError;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tracing::{info, warn, error};
+
+use crate::db::QuietAction;
+use crate::discord::{notify_user, quiet, TriggerEvent};
+
+
+/// Attempts before an event is dead-lettered
+const MAX_ATTEMPTS: i64 = 8;
+const RETRY_INITIAL_SECS: i64 = 5;
+const RETRY_MAX_SECS: i64 = 60 * 60;
+/// Events picked up per pass
+const BATCH_SIZE: i64 = 50;
+/// Events of a single user picked up per pass, so one busy user can't starve the rest
+const USER_BATCH_SIZE: i64 = 5;
+/// Users getting DMs at the same time, serenity's ratelimiter queues whatever Discord won't take yet
+const SEND_CONCURRENCY: usize = 8;
+/// Pending events per user, the rest is only counted and summarized once the backlog is sent
+const MAX_PENDING_PER_USER: i64 = 100;
+/// Events held during quiet hours are checked again this often
+const QUIET_RECHECK_SECS: i64 = 5 * 60;
+/// Check for due retries at least this often
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+/// Dead letters are kept this long for inspection
+const DEAD_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+/// Discord error codes meaning the bot can't DM the user at all:
+/// Unknown User, Cannot send messages to this user (DMs off, blocked, no mutual guild)
+const UNDELIVERABLE_CODES: [isize; 2] = [10013, 50007];
+/// Undeliverable errors in a row before the user is not matched anymore
+const UNDELIVERABLE_AFTER: i64 = 3;
+
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("SQL error: {0}")]
+    SqlxError(#[from] sqlx::Error),
+    #[error("Serialization error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+/// Events waiting for delivery, stored in the `outbox` table so they survive failures and restarts
+#[derive(Clone)]
+pub struct Outbox {
+    pool: sqlx::SqlitePool,
+    wake: Arc<Notify>,
+}
+
+impl Outbox {
+    pub fn new(pool: sqlx::SqlitePool) -> Self {
+        Self {
+            pool,
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    pub async fn push(&self, event: TriggerEvent) -> Result<(), OutboxError> {
+        let receiver = event.receiver() as i64;
+        // Retractions always go out, the DM they fix might have been sent before (or the backlog filled up since)
+        if matches!(event, TriggerEvent::Retract { .. }) {
+            return self.insert(receiver, &event).await;
+        }
+        if quiet::quiet_now(&mut *self.pool.acquire().await?, receiver).await? == Some(QuietAction::Drop) {
+            return Ok(());
+        }
+        let pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM outbox WHERE discord_user_id = ? AND status = 'pending'"#, receiver)
+            .fetch_one(&self.pool).await?
+            .count;
+        if pending >= MAX_PENDING_PER_USER {
+            sqlx::query!("INSERT INTO outbox_overflow (discord_user_id, dropped) VALUES (?, 1)
+                    ON CONFLICT(discord_user_id) DO UPDATE SET dropped = dropped + 1",
+                receiver)
+                .execute(&self.pool).await?;
+            return Ok(());
+        }
+        self.insert(receiver, &event).await
+    }
+
+    async fn insert(&self, receiver: i64, event: &TriggerEvent) -> Result<(), OutboxError> {
+        let payload = serde_json::to_string(event)?;
+        let now = chrono::Utc::now().timestamp();
+        sqlx::query!("INSERT INTO outbox (discord_user_id, payload, status, attempts, next_attempt_at, created_at) VALUES (?, ?, 'pending', 0, ?, ?)",
+            receiver,
+            payload,
+            now,
+            now)
+            .execute(&self.pool).await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Deliver pending events, including the ones left over from the last run
+    pub async fn run(self, cache_and_http: Arc<CacheAndHttp>) {
+        let pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!: i64" FROM outbox WHERE status = 'pending'"#)
+            .fetch_one(&self.pool).await
+            .map(|row| row.count)
+            .unwrap_or(0);
+        if pending > 0 {
+            info!("[DS] Replaying {} pending notifications", pending);
+        }
+
+        loop {
+            let wait = match self.deliver_due(&cache_and_http).await {
+                Ok(wait) => wait,
+                Err(e) => {
+                    error!("[DS] Error delivering notifications: {}", e);
+                    IDLE_WAIT
+                }
+            };
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    /// Try every due event once, returns how long until the next retry is due
+    async fn deliver_due(&self, cache_and_http: &Arc<CacheAndHttp>) -> Result<Duration, OutboxError> {
+        loop {
+            let now = chrono::Utc::now().timestamp();
+            // Round-robin: the first event of every user, then the second one and so on,
+            // never past an earlier event of the same user that is waiting for a retry
+            let rows = sqlx::query!(r#"SELECT id AS "id!: i64", discord_user_id AS "discord_user_id!: i64", payload AS "payload!: String", attempts AS "attempts!: i64"
+                    FROM (SELECT id, discord_user_id, payload, attempts, ROW_NUMBER() OVER (PARTITION BY discord_user_id ORDER BY id) AS position
+                          FROM outbox WHERE status = 'pending' AND next_attempt_at <= ?
+                          AND NOT EXISTS (SELECT 1 FROM outbox AS earlier WHERE earlier.discord_user_id = outbox.discord_user_id
+                              AND earlier.status = 'pending' AND earlier.id < outbox.id AND earlier.next_attempt_at > ?))
+                    WHERE position <= ? ORDER BY position, id LIMIT ?"#,
+                now,
+                now,
+                USER_BATCH_SIZE,
+                BATCH_SIZE)
+                .fetch_all(&self.pool).await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            let mut queues: AHashMap<i64, Vec<QueuedEvent>> = AHashMap::new();
+            for row in rows {
+                queues.entry(row.discord_user_id).or_default().push(QueuedEvent {
+                    id: row.id,
+                    payload: row.payload,
+                    attempts: row.attempts,
+                });
+            }
+            let results = futures_util::stream::iter(queues)
+                .map(|(discord_user_id, queue)| self.deliver_queue(cache_and_http, discord_user_id, queue))
+                .buffer_unordered(SEND_CONCURRENCY)
+                .collect::<Vec<_>>().await;
+            results.into_iter().collect::<Result<(), _>>()?;
+        }
+
+        self.summarize_overflow().await?;
+
+        let expired = chrono::Utc::now().timestamp() - DEAD_RETENTION_SECS;
+        sqlx::query!("DELETE FROM outbox WHERE status = 'dead' AND created_at < ?", expired)
+            .execute(&self.pool).await?;
+
+        let next = sqlx::query!(r#"SELECT MIN(next_attempt_at) AS "next: i64" FROM outbox WHERE status = 'pending'"#)
+            .fetch_one(&self.pool).await?
+            .next;
+        Ok(match next {
+            Some(next) => Duration::from_secs((next - chrono::Utc::now().timestamp()).clamp(1, IDLE_WAIT.as_secs() as i64) as u64),
+            None => IDLE_WAIT,
+        })
+    }
+
+    /// Send the events of one user in order
+    async fn deliver_queue(&self, cache_and_http: &Arc<CacheAndHttp>, discord_user_id: i64, queue: Vec<QueuedEvent>) -> Result<(), OutboxError> {
+        if quiet::quiet_now(&mut *self.pool.acquire().await?, discord_user_id).await?.is_some() {
+            let next_attempt_at = chrono::Utc::now().timestamp() + QUIET_RECHECK_SECS;
+            sqlx::query!("UPDATE outbox SET next_attempt_at = ? WHERE discord_user_id = ? AND status = 'pending'",
+                next_attempt_at,
+                discord_user_id)
+                .execute(&self.pool).await?;
+            return Ok(());
+        }
+        for queued in queue {
+            let event = match serde_json::from_str::<TriggerEvent>(&queued.payload) {
+                Ok(event) => event,
+                Err(e) => {
+                    self.dead_letter(queued.id, &e.to_string()).await?;
+                    continue;
+                }
+            };
+            match notify_user(cache_and_http.clone(), &self.pool, event).await {
+                Ok(_) => {
+                    sqlx::query!("DELETE FROM outbox WHERE id = ?", queued.id)
+                        .execute(&self.pool).await?;
+                    sqlx::query!("UPDATE dm_status SET failures = 0 WHERE discord_user_id = ? AND undeliverable_since IS NULL", discord_user_id)
+                        .execute(&self.pool).await?;
+                }
+                // Retrying won't help, the user has to change something first
+                Err(e) if is_undeliverable(&e) => {
+                    self.record_undeliverable(discord_user_id).await?;
+                    self.dead_letter(queued.id, &e.to_string()).await?;
+                    break;
+                }
+                Err(e) => {
+                    let attempts = queued.attempts + 1;
+                    let error = e.to_string();
+                    if attempts >= MAX_ATTEMPTS {
+                        // The rest is picked up again by the next pass, still in order
+                        self.dead_letter(queued.id, &error).await?;
+                        break;
+                    }
+                    warn!("[DS] Error sending direct message (attempt {}): {}", attempts, error);
+                    let next_attempt_at = chrono::Utc::now().timestamp() + retry_delay(attempts);
+                    sqlx::query!("UPDATE outbox SET attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?",
+                        attempts,
+                        next_attempt_at,
+                        error,
+                        queued.id)
+                        .execute(&self.pool).await?;
+                    // Later events wait behind this one, so the DMs keep their order
+                    sqlx::query!("UPDATE outbox SET next_attempt_at = MAX(next_attempt_at, ?) WHERE discord_user_id = ? AND status = 'pending' AND id > ?",
+                        next_attempt_at,
+                        discord_user_id,
+                        queued.id)
+                        .execute(&self.pool).await?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue "and N more" for users whose backlog was capped and has been sent since
+    async fn summarize_overflow(&self) -> Result<(), OutboxError> {
+        let rows = sqlx::query!("SELECT discord_user_id, dropped FROM outbox_overflow
+                WHERE discord_user_id NOT IN (SELECT discord_user_id FROM outbox WHERE status = 'pending')")
+            .fetch_all(&self.pool).await?;
+        for row in rows {
+            sqlx::query!("DELETE FROM outbox_overflow WHERE discord_user_id = ?", row.discord_user_id)
+                .execute(&self.pool).await?;
+            let event = TriggerEvent::Overflow {
+                receiver: row.discord_user_id as u64,
+                dropped: row.dropped,
+                timestamp: chrono::Utc::now(),
+            };
+            self.insert(row.discord_user_id, &event).await?;
+        }
+        Ok(())
+    }
+
+    /// Count an undeliverable DM, stop matching for the user after a few in a row
+    async fn record_undeliverable(&self, discord_user_id: i64) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let failures = sqlx::query!("INSERT INTO dm_status (discord_user_id, failures) VALUES (?, 1)
+                ON CONFLICT(discord_user_id) DO UPDATE SET failures = failures + 1
+                RETURNING failures",
+            discord_user_id)
+            .fetch_one(&self.pool).await?
+            .failures;
+        if failures == UNDELIVERABLE_AFTER {
+            warn!("[DS] Can't DM {}, not matching for them until they DM the bot", discord_user_id);
+            sqlx::query!("UPDATE dm_status SET undeliverable_since = ? WHERE discord_user_id = ?", now, discord_user_id)
+                .execute(&self.pool).await?;
+            sqlx::query!("UPDATE outbox SET status = 'dead', last_error = 'undeliverable' WHERE discord_user_id = ? AND status = 'pending'", discord_user_id)
+                .execute(&self.pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn dead_letter(&self, id: i64, error: &str) -> Result<(), sqlx::Error> {
+        error!("[DS] Giving up on notification {}: {}", id, error);
+        sqlx::query!("UPDATE outbox SET status = 'dead', last_error = ? WHERE id = ?", error, id)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+struct QueuedEvent {
+    id: i64,
+    payload: String,
+    attempts: i64,
+}
+
+fn is_undeliverable(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Http(e) => matches!(e.as_ref(),
+            HttpError::UnsuccessfulRequest(response) if UNDELIVERABLE_CODES.contains(&response.error.code)),
+        _ => false,
+    }
+}
+
+/// The user DMed the bot, so DMs work again
+pub async fn resume_delivery(pool: &sqlx::SqlitePool, discord_user_id: i64) -> Result<(), sqlx::Error> {
+    let res = sqlx::query!("DELETE FROM dm_status WHERE discord_user_id = ?", discord_user_id)
+        .execute(pool).await?;
+    if res.rows_affected() > 0 {
+        info!("[DS] {} messaged the bot, delivering DMs again", discord_user_id);
+    }
+    Ok(())
+}
+
+/// Exponential backoff with jitter, in seconds
+fn retry_delay(attempts: i64) -> i64 {
+    let delay = (RETRY_INITIAL_SECS << (attempts - 1).min(20)).min(RETRY_MAX_SECS);
+    delay / 2 + rand::thread_rng().gen_range(0..=delay / 2)
+}
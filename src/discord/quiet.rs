@@ -0,0 +1,101 @@
+use chrono::{Datelike, Timelike, Weekday};
+use chrono_tz::Tz;
+
+use crate::db::{QuietAction, QuietDays};
+
+
+impl QuietDays {
+    pub fn matches(&self, weekday: Weekday) -> bool {
+        match self {
+            QuietDays::All => true,
+            QuietDays::Weekdays => !matches!(weekday, Weekday::Sat | Weekday::Sun),
+            QuietDays::Weekends => matches!(weekday, Weekday::Sat | Weekday::Sun),
+            QuietDays::Mon => weekday == Weekday::Mon,
+            QuietDays::Tue => weekday == Weekday::Tue,
+            QuietDays::Wed => weekday == Weekday::Wed,
+            QuietDays::Thu => weekday == Weekday::Thu,
+            QuietDays::Fri => weekday == Weekday::Fri,
+            QuietDays::Sat => weekday == Weekday::Sat,
+            QuietDays::Sun => weekday == Weekday::Sun,
+        }
+    }
+}
+
+/// `HH:MM` of a minute of the day
+pub fn fmt_minute(minute: i64) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// The user's timezone, UTC if it's not set or not known anymore
+pub fn timezone(name: &str) -> Tz {
+    name.parse().unwrap_or(Tz::UTC)
+}
+
+/// What to do with notifications right now, `None` outside of the user's quiet hours
+pub async fn quiet_now(con: &mut sqlx::SqliteConnection, discord_user_id: i64) -> Result<Option<QuietAction>, sqlx::Error> {
+    let windows = sqlx::query!(r#"SELECT days AS "days: QuietDays", start_minute, end_minute FROM quiet_hours WHERE discord_user_id = ?"#,
+        discord_user_id)
+        .fetch_all(&mut *con).await?;
+    if windows.is_empty() {
+        return Ok(None);
+    }
+
+    let settings = crate::db::get_settings(con, discord_user_id).await?;
+    let now = chrono::Utc::now().with_timezone(&timezone(&settings.timezone));
+    let minute = (now.hour() * 60 + now.minute()) as i64;
+    let quiet = windows.iter()
+        .any(|window| is_within(window.days, window.start_minute, window.end_minute, now.weekday(), minute));
+    Ok(quiet.then_some(settings.quiet_action))
+}
+
+/// Whether `minute` of `weekday` falls in the window, windows past midnight belong to the day they start on
+fn is_within(days: QuietDays, start: i64, end: i64, weekday: Weekday, minute: i64) -> bool {
+    if start == end {
+        days.matches(weekday)
+    } else if start < end {
+        days.matches(weekday) && start <= minute && minute < end
+    } else {
+        (days.matches(weekday) && minute >= start) || (days.matches(weekday.pred()) && minute < end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_within_a_day() {
+        assert!(is_within(QuietDays::All, 9 * 60, 17 * 60, Weekday::Wed, 9 * 60));
+        assert!(is_within(QuietDays::All, 9 * 60, 17 * 60, Weekday::Wed, 16 * 60 + 59));
+        assert!(!is_within(QuietDays::All, 9 * 60, 17 * 60, Weekday::Wed, 17 * 60));
+        assert!(!is_within(QuietDays::All, 9 * 60, 17 * 60, Weekday::Wed, 8 * 60));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        // 22:00–07:00
+        assert!(is_within(QuietDays::All, 22 * 60, 7 * 60, Weekday::Wed, 23 * 60));
+        assert!(is_within(QuietDays::All, 22 * 60, 7 * 60, Weekday::Thu, 0));
+        assert!(is_within(QuietDays::All, 22 * 60, 7 * 60, Weekday::Thu, 6 * 60 + 59));
+        assert!(!is_within(QuietDays::All, 22 * 60, 7 * 60, Weekday::Thu, 7 * 60));
+        assert!(!is_within(QuietDays::All, 22 * 60, 7 * 60, Weekday::Thu, 12 * 60));
+    }
+
+    #[test]
+    fn weekend_window_into_monday() {
+        // Sunday 22:00 until Monday 07:00 belongs to Sunday
+        assert!(is_within(QuietDays::Weekends, 22 * 60, 7 * 60, Weekday::Sun, 23 * 60));
+        assert!(is_within(QuietDays::Weekends, 22 * 60, 7 * 60, Weekday::Mon, 6 * 60));
+        // Friday night is not part of the weekend, so Saturday morning isn't either
+        assert!(!is_within(QuietDays::Weekends, 22 * 60, 7 * 60, Weekday::Fri, 23 * 60));
+        assert!(!is_within(QuietDays::Weekends, 22 * 60, 7 * 60, Weekday::Sat, 6 * 60));
+        assert!(!is_within(QuietDays::Weekends, 22 * 60, 7 * 60, Weekday::Mon, 23 * 60));
+    }
+
+    #[test]
+    fn same_start_and_end_is_the_whole_day() {
+        assert!(is_within(QuietDays::Sat, 8 * 60, 8 * 60, Weekday::Sat, 0));
+        assert!(is_within(QuietDays::Sat, 8 * 60, 8 * 60, Weekday::Sat, 23 * 60 + 59));
+        assert!(!is_within(QuietDays::Sat, 8 * 60, 8 * 60, Weekday::Sun, 8 * 60));
+    }
+}
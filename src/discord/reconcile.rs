@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::Duration;
+use ahash::AHashSet;
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::prelude::*;
+use tracing::{debug, info, error};
+
+use crate::discord::{make_activity, ChannelCount, CommandPrefix};
+use crate::twitch::{ConnectionState, SupervisorHandle};
+
+
+const RECONCILE_EVERY: Duration = Duration::from_secs(5 * 60);
+
+/// Keep joined channels in line with the database and fix the channel count
+///
+/// A channel is only joined or parted after it was off in two passes in a row,
+/// so JOINs still waiting for the rate limit are not sent twice.
+pub async fn run(pool: sqlx::SqlitePool, irc: SupervisorHandle, data: Arc<RwLock<TypeMap>>, shard_manager: Arc<Mutex<ShardManager>>) {
+    let mut interval = tokio::time::interval(RECONCILE_EVERY);
+    let mut missing_before = AHashSet::new();
+    let mut extra_before = AHashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let desired = match sqlx::query!(r#"SELECT channel AS "channel!" FROM joined_channels"#)
+            .fetch_all(&pool).await {
+            Ok(rows) => rows.into_iter()
+                .map(|row| format!("#{}", row.channel))
+                .collect::<AHashSet<String>>(),
+            Err(e) => {
+                error!("[IRC] Error fetching channels to reconcile: {}", e);
+                continue;
+            }
+        };
+
+        update_channel_count(&data, &shard_manager, desired.len() as i32).await;
+
+        if *irc.state().borrow() != ConnectionState::Connected {
+            missing_before.clear();
+            extra_before.clear();
+            continue;
+        }
+
+        let joined = irc.joined();
+        let missing = desired.difference(&joined).cloned().collect::<AHashSet<_>>();
+        let extra = joined.difference(&desired).cloned().collect::<AHashSet<_>>();
+
+        let to_join = missing.intersection(&missing_before).cloned().collect::<Vec<_>>();
+        let to_part = extra.intersection(&extra_before).cloned().collect::<Vec<_>>();
+        if !to_join.is_empty() || !to_part.is_empty() {
+            info!("[IRC] Reconciling channels, joining {:?}, leaving {:?}", to_join, to_part);
+        } else {
+            debug!("[IRC] Channels reconciled, {} joined, {} missing, {} extra", joined.len(), missing.len(), extra.len());
+        }
+        for channel in to_join {
+            let _ = irc.join(channel);
+        }
+        for channel in to_part {
+            let _ = irc.part(channel);
+        }
+
+        missing_before = missing;
+        extra_before = extra;
+    }
+}
+
+async fn update_channel_count(data: &Arc<RwLock<TypeMap>>, shard_manager: &Arc<Mutex<ShardManager>>, channel_count: i32) {
+    let prefix = {
+        let mut data = data.write().await;
+        if data.get::<ChannelCount>() == Some(&channel_count) {
+            return;
+        }
+        data.insert::<ChannelCount>(channel_count);
+        data.get::<CommandPrefix>().unwrap().clone()
+    };
+
+    let manager = shard_manager.lock().await;
+    for runner in manager.runners.lock().await.values() {
+        runner.runner_tx.set_activity(Some(make_activity(channel_count, &prefix)));
+    }
+}
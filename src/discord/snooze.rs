@@ -0,0 +1,47 @@
+use std::time::Duration;
+use tracing::error;
+
+use crate::discord::outbox::{Outbox, OutboxError};
+use crate::discord::TriggerEvent;
+use crate::styled_str::escape_twitch_channel;
+
+
+const CHECK_EVERY: Duration = Duration::from_secs(30);
+
+/// Remove expired snoozes and let their users know
+pub async fn run(pool: sqlx::SqlitePool, outbox: Outbox) {
+    let mut interval = tokio::time::interval(CHECK_EVERY);
+    loop {
+        interval.tick().await;
+        if let Err(e) = expire(&pool, &outbox).await {
+            error!("[DS] Error expiring snoozes: {}", e);
+        }
+    }
+}
+
+async fn expire(pool: &sqlx::SqlitePool, outbox: &Outbox) -> Result<(), OutboxError> {
+    let now = chrono::Utc::now().timestamp();
+    let expired = sqlx::query!("SELECT snoozes.id, snoozes.discord_user_id, snoozes.channel, snoozes.trigger_id, triggers.trigger AS \"trigger?\" FROM snoozes
+            LEFT JOIN triggers ON triggers.id = snoozes.trigger_id
+            WHERE snoozes.until <= ?",
+        now)
+        .fetch_all(pool).await?;
+
+    for row in expired {
+        sqlx::query!("DELETE FROM snoozes WHERE id = ?", row.id)
+            .execute(pool).await?;
+        let what = match (row.channel, row.trigger_id, row.trigger) {
+            (Some(channel), _, _) => format!("#{}", escape_twitch_channel(&channel)),
+            (None, Some(_), Some(trigger)) => format!("`{}`", trigger),
+            // The trigger is gone, nothing to wake up
+            (None, Some(_), None) => continue,
+            (None, None, _) => "Everything".to_string(),
+        };
+        outbox.push(TriggerEvent::SnoozeEnded {
+            receiver: row.discord_user_id as u64,
+            what,
+            timestamp: chrono::Utc::now(),
+        }).await?;
+    }
+    Ok(())
+}
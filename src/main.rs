@@ -1,7 +1,6 @@
-use std::sync::Arc;
 use dotenvy::dotenv;
-use tokio::sync::{mpsc, RwLock};
-use tracing::{trace, debug, info, error, warn};
+use tokio::sync::mpsc;
+use tracing::{debug, info, error};
 
 mod discord;
 mod twitch;
@@ -13,11 +12,16 @@ use crate::twitch::IrcMessageEvent;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    init_tracing();
     info!("Starting...");
 
     dotenv().expect("Failed to load .env file");
 
+    let irc_settings = twitch::IrcSettings::from_env().expect("Invalid IRC settings");
+    debug!("IRC settings: {:?}", irc_settings);
+    let chat_log_settings = twitch::chatlog::ChatLogSettings::from_env().expect("Invalid chat log settings");
+    let eventsub_settings = twitch::eventsub::EventSubSettings::from_env().expect("Invalid EventSub settings");
+
     let db_pool = db::setup()
         .await.expect("Failed to setup database");
     let discord_db_con = db_pool.acquire()
@@ -25,86 +29,95 @@ async fn main() {
     let twitch_db_con = db_pool.acquire()
         .await.expect("Failed to acquire database connection");
 
-    let (discord_tx, mut discord_rx) = mpsc::channel::<TriggerEvent>(10_000);
+    let outbox = discord::outbox::Outbox::new(db_pool.clone());
     let (irc_tx, mut irc_rx) = mpsc::channel::<IrcMessageEvent>(10_000);
+    let (mirror_tx, mirror_rx) = mpsc::channel::<discord::mirror::MirrorLine>(10_000);
+    let chat_log_tx = chat_log_settings.clone().map(|settings| {
+        let (chat_log_tx, chat_log_rx) = mpsc::channel::<twitch::chatlog::ChatLogLine>(10_000);
+        tokio::spawn(twitch::chatlog::run(settings, chat_log_rx));
+        chat_log_tx
+    });
+    let (raid_tx, mut raid_rx) = mpsc::channel::<twitch::eventsub::OutgoingRaid>(100);
+    match eventsub_settings {
+        Some(settings) => {
+            tokio::spawn(twitch::eventsub::run(settings, db_pool.clone(), raid_tx));
+        }
+        None => info!("No `TWITCH_CLIENT_ID`/`TWITCH_EVENTSUB_TOKEN`, only raids seen in chat are followed"),
+    }
     let irc_tx_for_irc = irc_tx.clone();
 
+    let (mut twitch_client, supervisor) = twitch::make_client(twitch_db_con, outbox.clone(), irc_settings, irc_tx_for_irc, mirror_tx, chat_log_tx).await;
+    let supervisor_handle = supervisor.handle();
+    let connection_state = supervisor_handle.state();
+    let reconcile_irc = supervisor_handle.clone();
+    let chat_history = twitch_client.history();
+
     // Run discord bot
     let discord_handle = tokio::spawn(async move {
-        let mut client = discord::make_client(discord_db_con, irc_tx).await;
+        let mut client = discord::make_client(discord_db_con, irc_tx, chat_history, chat_log_settings, db_pool.clone()).await;
 
         let cache_and_http = client.cache_and_http.clone();
 
-        tokio::spawn(async move {
-            while let Some(event) = discord_rx.recv().await {
-                match discord::notify_user(cache_and_http.clone(), event).await {
-                    Ok(_) => {},
-                    Err(e) => {
-                        error!("[DS] Error sending direct message: {}", e);
-                    }
-                }
-            }
-        });
+        tokio::spawn(discord::gaps::watch(db_pool.clone(), connection_state, outbox.clone()));
+        tokio::spawn(discord::mirror::run(mirror_rx, cache_and_http.clone()));
+        tokio::spawn(discord::reconcile::run(db_pool.clone(), reconcile_irc, client.data.clone(), client.shard_manager.clone()));
+
+        tokio::spawn(discord::digest::run(db_pool.clone(), outbox.clone()));
+        tokio::spawn(discord::snooze::run(db_pool.clone(), outbox.clone()));
+        tokio::spawn(discord::alerts::run(db_pool.clone(), outbox.clone()));
+        tokio::spawn(outbox.run(cache_and_http.clone()));
 
         if let Err(why) = client.start().await {
             error!("[DS] An error occurred while running the client: {:?}", why);
         }
     });
 
-    let twitch_client = twitch::make_client(twitch_db_con, discord_tx).await.expect("Failed to make twitch client");
-    let twitch_client = Arc::new(RwLock::new(twitch_client));
-    let twitch_client_clone = twitch_client.clone();
-    let mut twitch_msg_stream = twitch_client.write().await.stream().expect("Failed to get twitch message stream");
-
+    // Run twitch connection, reconnects on its own
+    let twitch_handle = tokio::spawn(supervisor.run());
 
-    // Run twitch listener
-    let twitch_handle = tokio::spawn(async move {
-        use futures_util::StreamExt; // for next()
-        while let Some(message) = twitch_msg_stream.next().await {
-            if let Ok(message) = message {
-                let res = irc_tx_for_irc.send(IrcMessageEvent::Incoming(message)).await;
-                if res.is_err() {
-                    error!("[IRC] Error sending message to irc thread: {:?}", res.err().unwrap());
+    tokio::spawn(async move {
+        let mut cleanup_interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        let mut pending_interval = tokio::time::interval(std::time::Duration::from_millis(500));
+        let mut presence_interval = tokio::time::interval(std::time::Duration::from_secs(15));
+        loop {
+            tokio::select! {
+                event = irc_rx.recv() => {
+                    match event {
+                        Some(IrcMessageEvent::Incoming(message)) => {
+                            let res = twitch_client.handle(&message).await;
+                            if let Err(e) = res {
+                                error!("[IRC] Error handling message: {:?}", e);
+                            }
+                        }
+                        Some(IrcMessageEvent::Outgoing(message)) => {
+                            // debug!("Sending message: {:?}", message);
+                            let res = supervisor_handle.send(message);
+                            if let Err(e) = res {
+                                error!("[IRC] Error queueing message: {:?}", e);
+                            }
+                        }
+                        None => break,
+                    }
                 }
-            } else {
-                let err = message.err().unwrap();
-                error!("[IRC] Error getting message from irc stream: {:?}", &err);
-                match err {
-                    irc::error::Error::PingTimeout |
-                    irc::error::Error::AsyncChannelClosed |
-                    irc::error::Error::Tls(_) |
-                    irc::error::Error::Io(_) => {
-                        warn!("[IRC] Connection error, reconnecting...");
-                        twitch_client_clone.write().await
-                            .restart().await.expect("Failed to restart twitch client");
-                    },
-                    _ => {
-                        error!("[IRC] Unknown connection error, reconnecting...");
-                        twitch_client_clone.write().await
-                            .restart().await.expect("Failed to restart twitch client");
+                Some(raid) = raid_rx.recv() => {
+                    if let Err(e) = twitch_client.follow_raid(raid).await {
+                        error!("[IRC] Error following raid: {:?}", e);
                     }
                 }
-            }
-        }
-    });
-
-    tokio::spawn(async move {
-        while let Some(event) = irc_rx.recv().await {
-            match event {
-                IrcMessageEvent::Incoming(message) => {
-                    let res = twitch_client.write().await.handle(&message).await;
-                    if let Err(e) = res {
-                        error!("[IRC] Error handling message: {:?}", e);
+                _ = pending_interval.tick() => {
+                    if let Err(e) = twitch_client.flush_pending().await {
+                        error!("[IRC] Error sending held notifications: {:?}", e);
                     }
                 }
-                IrcMessageEvent::Outgoing(message) => {
-                    // debug!("Sending message: {:?}", message);
-                    let res = twitch_client.read().await.send(message);
-                    if let Err(e) = res {
-                        error!("[IRC] Error sending message: {:?}", e);
-                        warn!("[IRC] Restarting client just in case");
-                        twitch_client.write().await
-                            .restart().await.expect("Failed to restart twitch client");
+                _ = presence_interval.tick() => {
+                    if let Err(e) = twitch_client.flush_presence().await {
+                        error!("[IRC] Error sending presence changes: {:?}", e);
+                    }
+                }
+                _ = cleanup_interval.tick() => {
+                    twitch_client.prune();
+                    if let Err(e) = twitch_client.expire_follows().await {
+                        error!("[IRC] Error expiring raid follows: {:?}", e);
                     }
                 }
             }
@@ -116,3 +129,22 @@ async fn main() {
 
 }
 
+
+/// Same as `tracing_subscriber::fmt::init()`, but never lets `irc` trace raw lines,
+/// those include `PASS oauth:...` when logging in with a token
+fn init_tracing() {
+    use std::str::FromStr;
+    use tracing::Level;
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::filter::{filter_fn, LevelFilter, Targets};
+
+    let targets = std::env::var("RUST_LOG").ok()
+        .and_then(|var| Targets::from_str(&var).ok())
+        .unwrap_or_else(|| Targets::new().with_default(LevelFilter::INFO));
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(targets)
+        .with(filter_fn(|meta| !(meta.target().starts_with("irc::") && *meta.level() == Level::TRACE)))
+        .init();
+}
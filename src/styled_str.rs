@@ -71,6 +71,38 @@ pub fn fmt_args_error(e: &clap::Error) -> String {
     format!("*Invalid command or arguments*\n{}", e.render())
 }
 
+/// Format seconds as `1h 2m 3s`, skipping zero parts
+pub fn fmt_duration(secs: i64) -> String {
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    let mut parts = Vec::new();
+    if h > 0 { parts.push(format!("{}h", h)); }
+    if m > 0 { parts.push(format!("{}m", m)); }
+    if s > 0 || parts.is_empty() { parts.push(format!("{}s", s)); }
+    parts.join(" ")
+}
+
+/// Parse durations like `2h`, `30m` or `1d12h` into seconds
+pub fn parse_duration(s: &str) -> Option<i64> {
+    let mut total = 0i64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<i64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    (number.is_empty() && total > 0).then_some(total)
+}
+
 // TODO: Escaping and un-escaping discord-flavored markdown
 
 pub fn escape_twitch_channel(channel: &str) -> String {
@@ -92,3 +124,28 @@ pub fn escape_twitch_message(text: &str) -> String {
 //         .replace('~', "\\~")
 //         .replace('`', "\\`")
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("2h"), Some(2 * 60 * 60));
+        assert_eq!(parse_duration("1h30m"), Some(90 * 60));
+        assert_eq!(parse_duration("1d12h"), Some(36 * 60 * 60));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("2w"), None);
+        assert_eq!(parse_duration("-2h"), None);
+        assert_eq!(parse_duration("9999999999999999999d"), None);
+        assert_eq!(parse_duration("106751991167301d"), None);
+    }
+}
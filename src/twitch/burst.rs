@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use ahash::AHashMap;
+use tokio::time::Instant;
+
+
+/// How many matching messages to quote in a burst summary
+const BURST_SAMPLES: usize = 5;
+
+/// Trigger that fires on many matches in a short time instead of on a single message
+#[derive(Debug, Clone)]
+pub struct BurstRule {
+    pub trigger: String,
+    pub regex: bool,
+    pub count: usize,
+    pub window: Duration,
+    /// Only count one match per author
+    pub distinct: bool,
+}
+
+/// Sent instead of the single messages once a burst trigger fires
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BurstSummary {
+    pub channel: String,
+    pub trigger: String,
+    /// Matches in the window (or distinct authors)
+    pub count: usize,
+    pub window: Duration,
+    pub distinct: bool,
+    /// Latest matching messages as `(author, message)`
+    pub samples: Vec<(String, String)>,
+}
+
+/// `(channel, discord_user_id, trigger, regex)`, same as the `triggers` UNIQUE constraint plus the channel
+type BurstKey = (String, i64, String, bool);
+
+#[derive(Debug)]
+struct Hit {
+    at: Instant,
+    author: String,
+    message: String,
+}
+
+#[derive(Debug, Default)]
+struct BurstWindow {
+    hits: VecDeque<Hit>,
+    /// Window of the rule, kept for pruning
+    window: Duration,
+    /// Matches are ignored until then after the trigger fired
+    cooldown_until: Option<Instant>,
+}
+
+/// Sliding windows of matches per burst trigger and channel
+#[derive(Debug, Default)]
+pub struct Bursts {
+    windows: AHashMap<BurstKey, BurstWindow>,
+}
+
+impl Bursts {
+    /// Record a match, returns the summary when the trigger fires
+    /// (the window starts over then, after a cooldown as long as the window)
+    pub fn hit(&mut self, channel: &str, discord_id: i64, rule: &BurstRule, author: &str, message: &str) -> Option<BurstSummary> {
+        let key = (channel.to_string(), discord_id, rule.trigger.clone(), rule.regex);
+        let burst = self.windows.entry(key).or_default();
+        burst.window = rule.window;
+
+        let now = Instant::now();
+        if burst.cooldown_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        burst.cooldown_until = None;
+        let hits = &mut burst.hits;
+        while hits.front().is_some_and(|hit| now.duration_since(hit.at) > rule.window) {
+            hits.pop_front();
+        }
+        if rule.distinct {
+            // Only the latest match of each author counts, so the length is the distinct count
+            hits.retain(|hit| hit.author != author);
+        }
+        hits.push_back(Hit { at: now, author: author.to_string(), message: message.to_string() });
+
+        if hits.len() < rule.count {
+            return None;
+        }
+
+        let summary = BurstSummary {
+            channel: channel.to_string(),
+            trigger: rule.trigger.clone(),
+            count: hits.len(),
+            window: rule.window,
+            distinct: rule.distinct,
+            samples: hits.iter()
+                .rev()
+                .take(BURST_SAMPLES)
+                .map(|hit| (hit.author.clone(), hit.message.clone()))
+                .collect(),
+        };
+        hits.clear();
+        burst.cooldown_until = Some(now + rule.window);
+        Some(summary)
+    }
+
+    /// Forget windows without recent matches or cooldown
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        self.windows.retain(|_, burst| {
+            burst.cooldown_until.is_some_and(|until| now < until)
+                || burst.hits.back().is_some_and(|hit| now.duration_since(hit.at) <= burst.window)
+        });
+    }
+}
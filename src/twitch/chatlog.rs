@@ -0,0 +1,194 @@
+use std::env;
+use std::fmt::Write as _; // import without risk of name clashing
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ahash::AHashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{info, error};
+
+use super::ChatLine;
+
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+/// Lines are buffered and appended to the files this often
+const WRITE_EVERY: Duration = Duration::from_secs(5);
+const SWEEP_EVERY: Duration = Duration::from_secs(60 * 60);
+/// Format of the timestamp starting every plain text line, sorts like the time it stands for
+pub const LINE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// Longest range `read_range` reads, one file per day
+pub const MAX_RANGE_DAYS: i64 = 7;
+
+#[derive(Debug, Error)]
+pub enum ChatLogSettingsError {
+    #[error("`{0}` is not a valid number of days")]
+    InvalidRetention(String),
+}
+
+/// Where chat logs go, logging is off without `CHAT_LOG_DIR`
+#[derive(Debug, Clone)]
+pub struct ChatLogSettings {
+    pub dir: PathBuf,
+    pub retention_days: i64,
+}
+
+impl ChatLogSettings {
+    pub fn from_env() -> Result<Option<Self>, ChatLogSettingsError> {
+        let Ok(dir) = env::var("CHAT_LOG_DIR") else {
+            return Ok(None);
+        };
+        let retention_days = match env::var("CHAT_LOG_RETENTION_DAYS") {
+            Ok(value) => value.parse::<i64>().ok()
+                .filter(|days| *days > 0)
+                .ok_or(ChatLogSettingsError::InvalidRetention(value))?,
+            Err(_) => DEFAULT_RETENTION_DAYS,
+        };
+        Ok(Some(Self { dir: PathBuf::from(dir), retention_days }))
+    }
+
+    /// `<dir>/<channel>/<YYYY-MM-DD>.<extension>`
+    pub fn path(&self, channel: &str, date: NaiveDate, extension: &str) -> PathBuf {
+        self.dir.join(channel).join(format!("{}.{}", date.format("%Y-%m-%d"), extension))
+    }
+}
+
+/// One line of the `.jsonl` files
+#[derive(serde::Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    author: &'a str,
+    message: &'a str,
+}
+
+#[derive(Debug)]
+pub struct ChatLogLine {
+    pub channel: String,
+    pub line: ChatLine,
+}
+
+/// Append chat to per-channel, per-day files (`.log` plain text and `.jsonl`), delete the expired ones
+pub async fn run(settings: ChatLogSettings, mut rx: mpsc::Receiver<ChatLogLine>) {
+    info!("Logging chat to {}, keeping {} days", settings.dir.display(), settings.retention_days);
+    let mut buffered: AHashMap<(String, NaiveDate), Vec<ChatLine>> = AHashMap::new();
+    let mut write_interval = tokio::time::interval(WRITE_EVERY);
+    let mut sweep_interval = tokio::time::interval(SWEEP_EVERY);
+
+    loop {
+        tokio::select! {
+            log_line = rx.recv() => {
+                let Some(ChatLogLine { channel, line }) = log_line else {
+                    write_buffered(&settings, &mut buffered).await;
+                    return;
+                };
+                buffered.entry((channel, line.timestamp.date_naive())).or_default().push(line);
+            }
+            _ = write_interval.tick() => {
+                write_buffered(&settings, &mut buffered).await;
+            }
+            _ = sweep_interval.tick() => {
+                if let Err(e) = sweep(&settings).await {
+                    error!("Error deleting old chat logs: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn write_buffered(settings: &ChatLogSettings, buffered: &mut AHashMap<(String, NaiveDate), Vec<ChatLine>>) {
+    for ((channel, date), lines) in buffered.drain() {
+        let mut text = String::new();
+        let mut json = String::new();
+        for line in &lines {
+            let _ = writeln!(text, "{} {}: {}", line.timestamp.format(LINE_TIME_FORMAT), line.author, line.message);
+            let json_line = JsonLine {
+                timestamp: line.timestamp.to_rfc3339(),
+                author: &line.author,
+                message: &line.message,
+            };
+            match serde_json::to_string(&json_line) {
+                Ok(json_line) => { let _ = writeln!(json, "{}", json_line); }
+                Err(e) => error!("Error serializing chat log line: {}", e),
+            }
+        }
+        for (extension, content) in [("log", text), ("jsonl", json)] {
+            let path = settings.path(&channel, date, extension);
+            if let Err(e) = append(&path, content.as_bytes()).await {
+                error!("Error writing chat log {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+async fn append(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(content).await
+}
+
+/// Delete the files of days past the retention
+async fn sweep(settings: &ChatLogSettings) -> std::io::Result<()> {
+    let oldest_kept = (Utc::now() - chrono::Duration::days(settings.retention_days)).date_naive();
+    let Ok(mut channels) = tokio::fs::read_dir(&settings.dir).await else {
+        return Ok(());
+    };
+    while let Some(channel) = channels.next_entry().await? {
+        if !channel.file_type().await?.is_dir() {
+            continue;
+        }
+        let mut files = tokio::fs::read_dir(channel.path()).await?;
+        while let Some(file) = files.next_entry().await? {
+            let name = file.file_name();
+            let Some(date) = name.to_str()
+                .and_then(|name| name.split('.').next())
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()) else {
+                continue;
+            };
+            if date < oldest_kept {
+                tokio::fs::remove_file(file.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Plain text lines of `channel` between `from` and `to` (inclusive), at most [`MAX_RANGE_DAYS`] apart
+pub async fn read_range(settings: &ChatLogSettings, channel: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> std::io::Result<String> {
+    if to - from > chrono::Duration::days(MAX_RANGE_DAYS) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "chat log range too long"));
+    }
+    let from_prefix = from.format(LINE_TIME_FORMAT).to_string();
+    let to_prefix = to.format(LINE_TIME_FORMAT).to_string();
+    let mut range = String::new();
+
+    let mut date = from.date_naive();
+    while date <= to.date_naive() {
+        match tokio::fs::read_to_string(settings.path(channel, date, "log")).await {
+            Ok(content) => {
+                for line in content.lines() {
+                    let Some(time) = line.get(..from_prefix.len()) else {
+                        continue;
+                    };
+                    if time >= from_prefix.as_str() && time <= to_prefix.as_str() {
+                        range.push_str(line);
+                        range.push('\n');
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let Some(next) = date.succ_opt() else {
+            break;
+        };
+        date = next;
+    }
+    Ok(range)
+}
@@ -0,0 +1,146 @@
+use ahash::AHashMap;
+use irc::client::prelude::*;
+
+
+/// Kind of channel event users can subscribe to, stored as text in `events.kind`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Channel gets raided
+    Raid,
+    /// Someone gifts a bunch of subs at once
+    Gifts,
+    /// Moderator announcement
+    Announcement,
+    /// A given user gets banned or timed out
+    Ban,
+    /// Emote-only or sub-only mode turned on/off
+    Modes,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Raid => "raid",
+            EventKind::Gifts => "gifts",
+            EventKind::Announcement => "announcement",
+            EventKind::Ban => "ban",
+            EventKind::Modes => "modes",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RoomMode {
+    EmoteOnly,
+    SubsOnly,
+}
+
+impl RoomMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomMode::EmoteOnly => "Emote-only",
+            RoomMode::SubsOnly => "Sub-only",
+        }
+    }
+}
+
+/// Something that happened in a channel, other than a chat message
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum ChannelEvent {
+    Raid { from: String, viewers: u32 },
+    GiftBomb { gifter: String, count: u32 },
+    Announcement { author: String, message: String },
+    /// `duration` in seconds, `None` for a permanent ban
+    Ban { username: String, duration: Option<u32> },
+    ModeChange { mode: RoomMode, enabled: bool },
+}
+
+impl ChannelEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ChannelEvent::Raid { .. } => EventKind::Raid,
+            ChannelEvent::GiftBomb { .. } => EventKind::Gifts,
+            ChannelEvent::Announcement { .. } => EventKind::Announcement,
+            ChannelEvent::Ban { .. } => EventKind::Ban,
+            ChannelEvent::ModeChange { .. } => EventKind::Modes,
+        }
+    }
+
+    /// Subscription target to match against `events.target`, empty if the kind has none
+    pub fn target(&self) -> &str {
+        match self {
+            ChannelEvent::Ban { username, .. } => username,
+            _ => "",
+        }
+    }
+}
+
+pub fn get_tag<'a>(message: &'a Message, name: &str) -> Option<&'a str> {
+    message.tags.as_ref()?
+        .iter()
+        .find(|tag| tag.0 == name)
+        .and_then(|tag| tag.1.as_deref())
+}
+
+/// Parse a `USERNOTICE`, `args` are `[channel, message?]`
+pub fn parse_usernotice(message: &Message, args: &[String]) -> Option<ChannelEvent> {
+    let login = get_tag(message, "login").unwrap_or("").to_string();
+    match get_tag(message, "msg-id")? {
+        "raid" => Some(ChannelEvent::Raid {
+            from: get_tag(message, "msg-param-login").unwrap_or(&login).to_string(),
+            viewers: get_tag(message, "msg-param-viewerCount")?.parse().ok()?,
+        }),
+        "submysterygift" => Some(ChannelEvent::GiftBomb {
+            gifter: login,
+            count: get_tag(message, "msg-param-mass-gift-count")?.parse().ok()?,
+        }),
+        "announcement" => Some(ChannelEvent::Announcement {
+            author: login,
+            message: args.get(1).cloned().unwrap_or_default(),
+        }),
+        _ => None,
+    }
+}
+
+/// Parse a `CLEARCHAT`, `args` are `[channel, username?]` (no username means the whole chat was cleared)
+pub fn parse_clearchat(message: &Message, args: &[String]) -> Option<ChannelEvent> {
+    Some(ChannelEvent::Ban {
+        username: args.get(1)?.to_lowercase(),
+        duration: get_tag(message, "ban-duration").and_then(|d| d.parse().ok()),
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RoomState {
+    emote_only: bool,
+    subs_only: bool,
+}
+
+/// Remembers `ROOMSTATE` per channel to report changes only
+#[derive(Debug, Default)]
+pub struct RoomStates {
+    channels: AHashMap<String, RoomState>,
+}
+
+impl RoomStates {
+    /// Returns the mode changes, the first `ROOMSTATE` after joining only sets the baseline
+    pub fn update(&mut self, channel: &str, message: &Message) -> Vec<ChannelEvent> {
+        let known = self.channels.contains_key(channel);
+        let state = self.channels.entry(channel.to_string()).or_default();
+        let mut changes = Vec::new();
+
+        let modes: [(RoomMode, &str, &mut bool); 2] = [
+            (RoomMode::EmoteOnly, "emote-only", &mut state.emote_only),
+            (RoomMode::SubsOnly, "subs-only", &mut state.subs_only),
+        ];
+        for (mode, tag, current) in modes {
+            if let Some(enabled) = get_tag(message, tag).map(|v| v == "1") {
+                if known && enabled != *current {
+                    changes.push(ChannelEvent::ModeChange { mode, enabled });
+                }
+                *current = enabled;
+            }
+        }
+        changes
+    }
+}
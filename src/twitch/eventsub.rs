@@ -0,0 +1,336 @@
+use std::env;
+use std::time::Duration;
+use ahash::{AHashMap, AHashSet};
+use async_tungstenite::tungstenite;
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{debug, info, error, warn};
+
+
+const WEBSOCKET_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+/// How often the subscriptions are matched against the monitored channels
+const SYNC_EVERY: Duration = Duration::from_secs(60);
+/// Slack on top of the keepalive Twitch announces in the welcome message
+const KEEPALIVE_SLACK: Duration = Duration::from_secs(10);
+/// Helix takes at most this many logins per `users` request
+const USERS_PER_REQUEST: usize = 100;
+/// `channel.raid` subscriptions a WebSocket session can hold (its cost limit)
+pub const RAID_CHANNELS_MAX: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum EventSubSettingsError {
+    #[error("`TWITCH_CLIENT_ID` and `TWITCH_EVENTSUB_TOKEN` have to be set together")]
+    IncompleteLogin,
+}
+
+/// App and user token for EventSub, raid following is off without them
+#[derive(Debug, Clone)]
+pub struct EventSubSettings {
+    client_id: String,
+    /// User access token, Helix wants it without the `oauth:` prefix
+    token: String,
+}
+
+impl EventSubSettings {
+    pub fn from_env() -> Result<Option<Self>, EventSubSettingsError> {
+        match (env::var("TWITCH_CLIENT_ID"), env::var("TWITCH_EVENTSUB_TOKEN")) {
+            (Ok(client_id), Ok(token)) => Ok(Some(Self {
+                client_id,
+                token: token.trim_start_matches("oauth:").to_string(),
+            })),
+            (Err(_), Err(_)) => Ok(None),
+            _ => Err(EventSubSettingsError::IncompleteLogin),
+        }
+    }
+}
+
+/// A monitored channel (`from`) started a raid on `to`
+#[derive(Debug)]
+pub struct OutgoingRaid {
+    pub from: String,
+    pub to: String,
+    pub viewers: u32,
+}
+
+#[derive(Debug, Error)]
+enum EventSubError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] tungstenite::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+}
+
+/// Why a session ended without an error
+#[derive(Debug)]
+enum SessionEnd {
+    /// Twitch moves us to another server, the subscriptions come along
+    Reconnect(String),
+    /// No message or keepalive in time
+    Stalled,
+    /// The server closed the socket
+    Closed,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsMessage {
+    metadata: WsMetadata,
+    #[serde(default)]
+    payload: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsMetadata {
+    message_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsSession {
+    id: String,
+    keepalive_timeout_seconds: Option<u64>,
+    reconnect_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaidEvent {
+    from_broadcaster_user_login: String,
+    to_broadcaster_user_login: String,
+    viewers: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct HelixData<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HelixUser {
+    id: String,
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HelixSubscription {
+    id: String,
+}
+
+/// `channel.raid` subscriptions of the current session, by channel
+#[derive(Default)]
+struct Subscriptions {
+    /// Channel -> subscription ID
+    active: AHashMap<String, String>,
+    /// Channels Twitch refused (usually the WebSocket cost limit), not retried this session
+    refused: AHashSet<String>,
+}
+
+/// Learn about raids leaving monitored channels, through EventSub over WebSocket
+///
+/// Twitch only announces a raid in the raided chat, where the bot usually isn't,
+/// so EventSub adds the raider's side to the raids seen on IRC.
+/// Only channels of users following raids (`settings raids`) are subscribed.
+///
+/// NOTE: Subscriptions over WebSocket count against a small cost limit (10 for `channel.raid`),
+///  channels past it are logged, skipped and marked refused in `raid_subscriptions`.
+pub async fn run(settings: EventSubSettings, pool: sqlx::SqlitePool, raid_tx: mpsc::Sender<OutgoingRaid>) {
+    let http = reqwest::Client::new();
+    let mut backoff = ExponentialBackoff {
+        max_interval: Duration::from_secs(5 * 60),
+        max_elapsed_time: None,
+        ..ExponentialBackoff::default()
+    };
+    let mut url = WEBSOCKET_URL.to_string();
+    let mut subscriptions = Subscriptions::default();
+    loop {
+        match session(&settings, &http, &pool, &raid_tx, &url, &mut subscriptions, &mut backoff).await {
+            Ok(SessionEnd::Reconnect(reconnect_url)) => {
+                info!("[EventSub] Asked to reconnect, moving to the new server");
+                url = reconnect_url;
+                continue;
+            }
+            Ok(end) => {
+                warn!("[EventSub] Connection ended ({:?}), reconnecting...", end);
+            }
+            Err(e) => {
+                error!("[EventSub] Connection error, reconnecting: {}", e);
+            }
+        }
+        // A new session starts without subscriptions
+        url = WEBSOCKET_URL.to_string();
+        subscriptions = Subscriptions::default();
+        let sleep_time = backoff.next_backoff().unwrap_or(backoff.max_interval);
+        tokio::time::sleep(sleep_time).await;
+    }
+}
+
+async fn session(settings: &EventSubSettings, http: &reqwest::Client, pool: &sqlx::SqlitePool,
+                 raid_tx: &mpsc::Sender<OutgoingRaid>, url: &str,
+                 subscriptions: &mut Subscriptions, backoff: &mut ExponentialBackoff) -> Result<SessionEnd, EventSubError> {
+    let (mut stream, _) = async_tungstenite::tokio::connect_async(url).await?;
+
+    let mut session_id = None;
+    let mut keepalive = Duration::from_secs(10);
+    let mut sync_interval = tokio::time::interval(SYNC_EVERY);
+
+    loop {
+        tokio::select! {
+            message = tokio::time::timeout(keepalive + KEEPALIVE_SLACK, stream.next()) => {
+                let message = match message {
+                    Ok(Some(message)) => message?,
+                    Ok(None) => return Ok(SessionEnd::Closed),
+                    Err(_) => return Ok(SessionEnd::Stalled),
+                };
+                let text = match message {
+                    tungstenite::Message::Text(text) => text,
+                    tungstenite::Message::Close(_) => return Ok(SessionEnd::Closed),
+                    // Pings are answered by the stream
+                    _ => continue,
+                };
+                let message = serde_json::from_str::<WsMessage>(&text)?;
+                match message.metadata.message_type.as_str() {
+                    "session_welcome" => {
+                        let session = serde_json::from_value::<WsSession>(message.payload["session"].clone())?;
+                        debug!("[EventSub] Session {} started", session.id);
+                        backoff.reset();
+                        if let Some(secs) = session.keepalive_timeout_seconds {
+                            keepalive = Duration::from_secs(secs);
+                        }
+                        sync_interval.reset();
+                        if let Err(e) = sync_subscriptions(settings, http, pool, &session.id, subscriptions).await {
+                            error!("[EventSub] Error syncing subscriptions: {}", e);
+                        }
+                        session_id = Some(session.id);
+                    }
+                    "session_reconnect" => {
+                        let session = serde_json::from_value::<WsSession>(message.payload["session"].clone())?;
+                        if let Some(reconnect_url) = session.reconnect_url {
+                            return Ok(SessionEnd::Reconnect(reconnect_url));
+                        }
+                    }
+                    "notification" if message.payload["subscription"]["type"] == "channel.raid" => {
+                        let event = serde_json::from_value::<RaidEvent>(message.payload["event"].clone())?;
+                        let raid = OutgoingRaid {
+                            from: event.from_broadcaster_user_login.to_lowercase(),
+                            to: event.to_broadcaster_user_login.to_lowercase(),
+                            viewers: event.viewers,
+                        };
+                        if let Err(e) = raid_tx.send(raid).await {
+                            error!("[EventSub] Error sending raid to irc thread: {:?}", e);
+                        }
+                    }
+                    "revocation" => {
+                        let id = message.payload["subscription"]["id"].as_str().unwrap_or_default();
+                        warn!("[EventSub] Subscription {} revoked", id);
+                        subscriptions.active.retain(|_, subscription_id| subscription_id != id);
+                    }
+                    _ => {}
+                }
+            }
+            _ = sync_interval.tick(), if session_id.is_some() => {
+                if let Some(session_id) = &session_id {
+                    if let Err(e) = sync_subscriptions(settings, http, pool, session_id, subscriptions).await {
+                        error!("[EventSub] Error syncing subscriptions: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Subscribe to raids of newly followed channels, drop the ones nobody follows anymore
+async fn sync_subscriptions(settings: &EventSubSettings, http: &reqwest::Client, pool: &sqlx::SqlitePool,
+                            session_id: &str, subscriptions: &mut Subscriptions) -> Result<(), EventSubError> {
+    let desired = sqlx::query!("SELECT DISTINCT channels.channel FROM channels INNER JOIN settings ON settings.discord_user_id = channels.discord_user_id WHERE settings.raid_follow_minutes > 0")
+        .fetch_all(pool).await?
+        .into_iter()
+        .map(|row| row.channel)
+        .collect::<AHashSet<String>>();
+
+    let unwanted = subscriptions.active.keys()
+        .filter(|channel| !desired.contains(*channel))
+        .cloned()
+        .collect::<Vec<_>>();
+    for channel in unwanted {
+        if let Some(id) = subscriptions.active.remove(&channel) {
+            let res = helix(http.delete(format!("{}/eventsub/subscriptions", HELIX_URL)), settings)
+                .query(&[("id", &id)])
+                .send().await?;
+            if !res.status().is_success() {
+                warn!("[EventSub] Failed to unsubscribe from raids of #{}: {}", channel, res.status());
+            }
+        }
+    }
+
+    let missing = desired.iter()
+        .filter(|channel| !subscriptions.active.contains_key(*channel) && !subscriptions.refused.contains(*channel))
+        .cloned()
+        .collect::<Vec<_>>();
+    for logins in missing.chunks(USERS_PER_REQUEST) {
+        let query = logins.iter().map(|login| ("login", login)).collect::<Vec<_>>();
+        let users = helix(http.get(format!("{}/users", HELIX_URL)), settings)
+            .query(&query)
+            .send().await?
+            .error_for_status()?
+            .json::<HelixData<HelixUser>>().await?
+            .data;
+        for login in logins {
+            if !users.iter().any(|user| &user.login == login) {
+                warn!("[EventSub] No Twitch user #{}, not watching its raids", login);
+                subscriptions.refused.insert(login.clone());
+            }
+        }
+
+        for user in users {
+            let body = serde_json::json!({
+                "type": "channel.raid",
+                "version": "1",
+                "condition": { "from_broadcaster_user_id": user.id },
+                "transport": { "method": "websocket", "session_id": session_id },
+            });
+            let res = helix(http.post(format!("{}/eventsub/subscriptions", HELIX_URL)), settings)
+                .json(&body)
+                .send().await?;
+            if !res.status().is_success() {
+                warn!("[EventSub] Twitch refused to send raids of #{}: {} {}", user.login, res.status(), res.text().await.unwrap_or_default());
+                subscriptions.refused.insert(user.login);
+                continue;
+            }
+            let Some(subscription) = res.json::<HelixData<HelixSubscription>>().await?.data.pop() else {
+                continue;
+            };
+            debug!("[EventSub] Watching raids of #{}", user.login);
+            subscriptions.active.insert(user.login, subscription.id);
+        }
+    }
+    save_status(pool, subscriptions).await?;
+    Ok(())
+}
+
+/// Keep the subscriptions in `raid_subscriptions`, so `settings raids` can tell which channels are refused
+async fn save_status(pool: &sqlx::SqlitePool, subscriptions: &Subscriptions) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM raid_subscriptions")
+        .execute(&mut tx).await?;
+    let statuses = subscriptions.active.keys().map(|channel| (channel, "active"))
+        .chain(subscriptions.refused.iter().map(|channel| (channel, "refused")));
+    for (channel, status) in statuses {
+        sqlx::query!("INSERT OR REPLACE INTO raid_subscriptions (channel, status) VALUES (?, ?)",
+            channel,
+            status)
+            .execute(&mut tx).await?;
+    }
+    tx.commit().await
+}
+
+fn helix(request: reqwest::RequestBuilder, settings: &EventSubSettings) -> reqwest::RequestBuilder {
+    request
+        .header("Client-Id", &settings.client_id)
+        .bearer_auth(&settings.token)
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+use ahash::{AHashMap, AHashSet};
+use tokio::time::Instant;
+
+
+/// Longest cooldown or duplicate window the settings allow
+pub const MAX_WINDOW_SECS: u16 = 60 * 60;
+/// Share of words two messages need in common to count as the same
+const DUPLICATE_SIMILARITY: f64 = 0.8;
+
+/// Flood control settings of a user, a zero duration turns that check off
+#[derive(Debug)]
+pub struct FloodRules {
+    /// Between any two notifications
+    pub cooldown: Duration,
+    /// Between two notifications of the same trigger
+    pub trigger_cooldown: Duration,
+    /// Near-identical messages of the same author within this window are dropped
+    pub duplicate_window: Duration,
+}
+
+/// Notifications recently sent to each user, to hold back copypasta storms
+#[derive(Debug, Default)]
+pub struct FloodControl {
+    last_sent: AHashMap<i64, Instant>,
+    /// `(discord_user_id, trigger ID)`
+    last_trigger: AHashMap<(i64, i64), Instant>,
+    /// `(discord_user_id, channel, author)` -> words of the last notified message
+    last_message: AHashMap<(i64, String, String), (Instant, AHashSet<String>)>,
+    /// Notifications held back since the last one sent
+    suppressed: AHashMap<i64, u64>,
+}
+
+impl FloodControl {
+    /// Whether a notification about `message` may go out now, it's counted as suppressed otherwise
+    ///
+    /// `trigger_ids` are the ones that matched, the trigger cooldown holds it back only if all of them are cooling down.
+    pub fn allow(&mut self, discord_id: i64, rules: &FloodRules, trigger_ids: &[i64],
+                 channel: &str, author: &str, message: &str) -> bool {
+        let now = Instant::now();
+        let words = words(message);
+        let message_key = (discord_id, channel.to_string(), author.to_string());
+
+        let cooling = self.last_sent.get(&discord_id)
+            .is_some_and(|last| now.duration_since(*last) < rules.cooldown);
+        let trigger_cooling = !trigger_ids.is_empty() && trigger_ids.iter().all(|trigger_id|
+            self.last_trigger.get(&(discord_id, *trigger_id))
+                .is_some_and(|last| now.duration_since(*last) < rules.trigger_cooldown));
+        let duplicate = self.last_message.get(&message_key)
+            .is_some_and(|(last, last_words)|
+                now.duration_since(*last) < rules.duplicate_window && similarity(&words, last_words) >= DUPLICATE_SIMILARITY);
+
+        if cooling || trigger_cooling || duplicate {
+            *self.suppressed.entry(discord_id).or_default() += 1;
+            return false;
+        }
+
+        self.last_sent.insert(discord_id, now);
+        for trigger_id in trigger_ids {
+            self.last_trigger.insert((discord_id, *trigger_id), now);
+        }
+        self.last_message.insert(message_key, (now, words));
+        true
+    }
+
+    /// Notifications held back since the last one sent, resets the count
+    pub fn take_suppressed(&mut self, discord_id: i64) -> u64 {
+        self.suppressed.remove(&discord_id).unwrap_or(0)
+    }
+
+    /// Forget notifications older than any window
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let max = Duration::from_secs(MAX_WINDOW_SECS as u64);
+        self.last_sent.retain(|_, last| now.duration_since(*last) < max);
+        self.last_trigger.retain(|_, last| now.duration_since(*last) < max);
+        self.last_message.retain(|_, (last, _)| now.duration_since(*last) < max);
+    }
+}
+
+/// Lowercase words, ignoring punctuation and the invisible characters used to get around Twitch's duplicate check
+fn words(message: &str) -> AHashSet<String> {
+    message.split_whitespace()
+        .map(|word| word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Jaccard index of two word sets
+fn similarity(a: &AHashSet<String>, b: &AHashSet<String>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / a.union(b).count() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_ignore_case_punctuation_and_invisible_characters() {
+        let expected = ["hello", "chat"].iter().map(|word| word.to_string()).collect::<AHashSet<_>>();
+        assert_eq!(words("Hello, chat!"), expected);
+        assert_eq!(words("hello chat \u{e0000}"), expected);
+        assert_eq!(words("HELLO   chat chat"), expected);
+        assert!(words("!!! ...").is_empty());
+    }
+
+    #[test]
+    fn similarity_of_word_sets() {
+        assert_eq!(similarity(&words("a b c"), &words("c b a")), 1.0);
+        assert_eq!(similarity(&words("a b"), &words("c d")), 0.0);
+        assert_eq!(similarity(&words("a b c"), &words("a b d")), 0.5);
+        assert_eq!(similarity(&words(""), &words("")), 1.0);
+        assert_eq!(similarity(&words("a"), &words("")), 0.0);
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+use ahash::AHashMap;
+use tokio::time::Instant;
+
+
+#[derive(Debug)]
+struct Receiver {
+    discord_id: i64,
+    until: Instant,
+    /// Triggers that matched, the follow-up stops once none of them is live
+    trigger_ids: Vec<i64>,
+}
+
+/// Authors whose next messages are forwarded after one of them triggered a notification
+#[derive(Debug, Default)]
+pub struct FollowUps {
+    /// `(channel, author)` -> receivers
+    authors: AHashMap<(String, String), Vec<Receiver>>,
+}
+
+impl FollowUps {
+    /// Forward `author`'s messages in `channel` to `discord_id` for `window`, or extend it
+    pub fn start(&mut self, channel: &str, author: &str, discord_id: i64, trigger_ids: &[i64], window: Duration) {
+        let until = Instant::now() + window;
+        let receivers = self.authors.entry((channel.to_string(), author.to_string())).or_default();
+        match receivers.iter_mut().find(|receiver| receiver.discord_id == discord_id) {
+            Some(receiver) => {
+                receiver.until = until;
+                for trigger_id in trigger_ids {
+                    if !receiver.trigger_ids.contains(trigger_id) {
+                        receiver.trigger_ids.push(*trigger_id);
+                    }
+                }
+            }
+            None => receivers.push(Receiver { discord_id, until, trigger_ids: trigger_ids.to_vec() }),
+        }
+    }
+
+    /// Users still following `author` in `channel`, with the triggers that started it
+    pub fn receivers(&mut self, channel: &str, author: &str) -> Vec<(i64, Vec<i64>)> {
+        let key = (channel.to_string(), author.to_string());
+        let Some(receivers) = self.authors.get_mut(&key) else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        receivers.retain(|receiver| receiver.until > now);
+        let ids = receivers.iter()
+            .map(|receiver| (receiver.discord_id, receiver.trigger_ids.clone()))
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            self.authors.remove(&key);
+        }
+        ids
+    }
+
+    /// Forget expired follow-ups of authors that went quiet
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        self.authors.retain(|_, receivers| {
+            receivers.retain(|receiver| receiver.until > now);
+            !receivers.is_empty()
+        });
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ahash::AHashMap;
+use tokio::time::Instant;
+
+use super::TwitchMessageSimple;
+
+
+/// Messages kept per channel
+pub const HISTORY_LINES: usize = 100;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChatLine {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub author: String,
+    pub message: String,
+}
+
+/// Ring buffer of the latest messages per channel, cheap to clone and share
+#[derive(Debug, Clone, Default)]
+pub struct ChatHistory {
+    channels: Arc<Mutex<AHashMap<String, VecDeque<ChatLine>>>>,
+}
+
+impl ChatHistory {
+    pub fn push(&self, channel: &str, line: ChatLine) {
+        let mut channels = self.channels.lock().unwrap();
+        let lines = channels.entry(channel.to_string()).or_default();
+        if lines.len() == HISTORY_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Up to `count` latest messages in `channel`, oldest first
+    pub fn last(&self, channel: &str, count: usize) -> Vec<ChatLine> {
+        let channels = self.channels.lock().unwrap();
+        let Some(lines) = channels.get(channel) else {
+            return Vec::new();
+        };
+        lines.iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Waiting {
+    until: Instant,
+    receiver: i64,
+    lines: usize,
+    message: TwitchMessageSimple,
+}
+
+/// Notifications waiting a moment to collect the lines following the trigger
+#[derive(Debug, Default)]
+pub struct PendingContext {
+    waiting: Vec<Waiting>,
+}
+
+impl PendingContext {
+    pub fn hold(&mut self, receiver: i64, message: TwitchMessageSimple, lines: usize, delay: Duration) {
+        self.waiting.push(Waiting { until: Instant::now() + delay, receiver, lines, message });
+    }
+
+    pub fn add_line(&mut self, channel: &str, line: &ChatLine) {
+        for waiting in self.waiting.iter_mut()
+            .filter(|w| w.message.channel == channel && w.message.context_after.len() < w.lines) {
+            waiting.message.context_after.push(line.clone());
+        }
+    }
+
+    /// Notifications done waiting, or with all the lines they wanted
+    pub fn take_ready(&mut self) -> Vec<(i64, TwitchMessageSimple)> {
+        let now = Instant::now();
+        let (ready, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition::<Vec<_>, _>(|w| w.until <= now || w.message.context_after.len() >= w.lines);
+        self.waiting = waiting;
+        ready.into_iter()
+            .map(|w| (w.receiver, w.message))
+            .collect()
+    }
+}
@@ -1,11 +1,33 @@
 use irc::client::prelude::*;
 use thiserror::Error;
 use ahash::AHashMap;
-use backoff::backoff::Backoff;
-use irc::client::ClientStream;
 use tracing::{trace, debug, info, error};
 
 use crate::TriggerEvent;
+use crate::discord::{digest, quiet};
+use crate::discord::mirror::MirrorLine;
+use crate::discord::outbox::Outbox;
+use crate::db::{DeliveryMode, OnDelete, QuietAction};
+
+mod burst;
+pub mod chatlog;
+mod events;
+pub mod eventsub;
+mod flood;
+mod followup;
+mod history;
+mod presence;
+mod settings;
+mod shared;
+mod supervisor;
+
+pub use burst::BurstSummary;
+pub use flood::MAX_WINDOW_SECS;
+pub use events::{ChannelEvent, EventKind};
+pub use history::{ChatHistory, ChatLine};
+pub use presence::PresenceChange;
+pub use settings::IrcSettings;
+pub use supervisor::{ConnectionState, Supervisor, SupervisorHandle};
 
 
 
@@ -25,24 +47,65 @@ pub fn make_part_msg(channel: String) -> IrcMessageEvent {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TwitchMessageSimple {
+    /// Twitch message id (`id` tag), empty if tags are missing
+    pub id: String,
     pub channel: String,
     pub author: String,
     pub message: String,
     pub triggers: Vec<(u16, u16)>,
+    /// Other channels the same shared chat message matched in
+    pub shared_with: Vec<String>,
+    /// Chat lines before and after the message, if the receiver wants context
+    pub context_before: Vec<ChatLine>,
+    pub context_after: Vec<ChatLine>,
+    /// Notifications held back by flood control since the last one
+    #[serde(default)]
+    pub suppressed: u64,
+    /// Set when an urgent trigger matched, the DM re-pings until acknowledged
+    #[serde(default)]
+    pub urgent: Option<Urgent>,
+    /// Database IDs of the triggers that matched, for the Disable trigger button
+    #[serde(default)]
+    pub trigger_ids: Vec<i64>,
+}
+
+/// Re-ping settings of an urgent match
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Urgent {
+    /// Re-ping this often
+    pub minutes: i64,
+    /// Also ping this Discord user once re-pings go unanswered
+    pub escalate_to: Option<i64>,
 }
 
 impl TwitchMessageSimple {
-    pub fn new(channel: String, author: String, message: String) -> Self {
+    pub fn new(id: String, channel: String, author: String, message: String) -> Self {
         Self {
+            id,
             channel,
             author,
             message,
             triggers: Vec::new(),
+            shared_with: Vec::new(),
+            context_before: Vec::new(),
+            context_after: Vec::new(),
+            suppressed: 0,
+            urgent: None,
+            trigger_ids: Vec::new(),
         }
     }
 
+    /// `#channel`, plus the shared chat channels if any
+    pub fn channels_display(&self) -> String {
+        std::iter::once(&self.channel)
+            .chain(self.shared_with.iter())
+            .map(|channel| format!("#{}", channel))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     pub fn add_trigger(&mut self, trig: (u16, u16)) {
         // Insert the trigger sorted by start position
         let len_before = self.triggers.len();
@@ -107,6 +170,29 @@ impl TwitchMessageSimple {
 
 
 
+/// Positions of `trigger` in `msg` as `(start, end)` byte offsets
+fn find_matches(trigger: &str, case_sensitive: bool, regex: bool, msg: &str) -> Vec<(u16, u16)> {
+    if regex {
+        // TODO: Make sure regex in DB is valid (check when putting in)
+        let re = if case_sensitive {
+            regex::Regex::new(trigger).unwrap()
+        } else {
+            regex::Regex::new(format!("(?i:{})", trigger).as_str()).unwrap()
+        };
+        re.find_iter(msg)
+            .map(|mat| (mat.start() as u16, mat.end() as u16))
+            .collect()
+    } else if case_sensitive {
+        msg.match_indices(trigger)
+            .map(|pos| (pos.0 as u16, (pos.0 + trigger.len()) as u16))
+            .collect()
+    } else {
+        msg.to_lowercase().match_indices(&trigger.to_lowercase())
+            .map(|pos| (pos.0 as u16, (pos.0 + trigger.len()) as u16))
+            .collect()
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum IrcThreadError {
     #[error("IRC error: {0}")]
@@ -118,90 +204,63 @@ pub enum IrcThreadError {
 }
 
 pub struct TwitchClient {
-    client: Client,
-    config: Config,
+    /// Our own nickname, known after `RPL_WELCOME`
+    nickname: String,
+    room_states: events::RoomStates,
+    irc: SupervisorHandle,
+    shared_chat: shared::SharedChat,
+    presence: presence::PresenceWatch,
+    bursts: burst::Bursts,
+    flood: flood::FloodControl,
+    followups: followup::FollowUps,
+    history: ChatHistory,
+    pending_context: history::PendingContext,
     db_con: tokio::sync::Mutex<sqlx::pool::PoolConnection<sqlx::Sqlite>>,
-    discord_tx: tokio::sync::mpsc::Sender<TriggerEvent>,
+    outbox: Outbox,
+    mirror_tx: tokio::sync::mpsc::Sender<MirrorLine>,
+    chat_log_tx: Option<tokio::sync::mpsc::Sender<chatlog::ChatLogLine>>,
 }
 
-pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>, tx: tokio::sync::mpsc::Sender<TriggerEvent>) -> Result<TwitchClient, irc::error::Error> {
-    // We can also load the Config at runtime via Config::load("path/to/config.toml")
-    let config = Config {
-        nickname: Some(format!("justinfan{}", rand::random::<u32>())),
-        alt_nicks: vec![ // Just in case the first one is taken
-                         format!("justinfan{}", rand::random::<u32>()),
-                         format!("justinfan{}", rand::random::<u32>()),
-                         format!("justinfan{}", rand::random::<u32>()),
-        ],
-        // realname: Some("Offline_Frog".to_string()),
-        use_tls: Some(true),
-        server: Some("irc.chat.twitch.tv".to_string()),
-        port: Some(6697),
-        channels: sqlx::query!("SELECT DISTINCT channel FROM channels")
-            .fetch_all(&mut db_con)
-            .await
-            .expect("Failed to fetch channels from DB")
-            .into_iter()
-            .map(|row| format!("#{}", row.channel))
-            .collect::<Vec<String>>(),
-        ..Config::default()
+/// Make the message handler and the supervisor owning the connection,
+/// incoming messages are sent to `irc_tx`
+pub async fn make_client(mut db_con: sqlx::pool::PoolConnection<sqlx::Sqlite>,
+                         outbox: Outbox,
+                         settings: IrcSettings,
+                         irc_tx: tokio::sync::mpsc::Sender<IrcMessageEvent>,
+                         mirror_tx: tokio::sync::mpsc::Sender<MirrorLine>,
+                         chat_log_tx: Option<tokio::sync::mpsc::Sender<chatlog::ChatLogLine>>) -> (TwitchClient, Supervisor) {
+    let channels = sqlx::query!(r#"SELECT channel AS "channel!" FROM joined_channels"#)
+        .fetch_all(&mut db_con)
+        .await
+        .expect("Failed to fetch channels from DB")
+        .into_iter()
+        .map(|row| format!("#{}", row.channel))
+        .collect::<Vec<String>>();
+
+    let supervisor = Supervisor::new(settings, channels, irc_tx);
+
+    let client = TwitchClient {
+        nickname: String::new(),
+        room_states: events::RoomStates::default(),
+        irc: supervisor.handle(),
+        shared_chat: shared::SharedChat::default(),
+        presence: presence::PresenceWatch::default(),
+        bursts: burst::Bursts::default(),
+        flood: flood::FloodControl::default(),
+        followups: followup::FollowUps::default(),
+        history: ChatHistory::default(),
+        pending_context: history::PendingContext::default(),
+        db_con: tokio::sync::Mutex::new(db_con),
+        outbox,
+        mirror_tx,
+        chat_log_tx,
     };
 
-    let client = Client::from_config(config.clone()).await?;
-    client.identify()?;
-    // client.send(Command::CAP(
-    //     None,
-    //     command::CapSubCommand::REQ,
-    //     None,
-    //     Some("twitch.tv/membership".to_string())))?;
-
-    Ok(TwitchClient {
-        client,
-        config,
-        db_con: tokio::sync::Mutex::new(db_con),
-        discord_tx: tx,
-    })
+    (client, supervisor)
 }
 
 impl TwitchClient {
 
-    pub fn send(&self, message: impl Into<Message>) -> Result<(), irc::error::Error> {
-        self.client.send(message)
-    }
-
-    pub fn stream(&mut self) -> Result<ClientStream, irc::error::Error> {
-        self.client.stream()
-    }
-
-    async fn restart_inner(&mut self) -> Result<(), irc::error::Error> {
-        let mut config = self.config.clone();
-        config.channels = self.client.list_channels().unwrap();
-        self.client = Client::from_config(config).await?;
-        Ok(())
-    }
-
-    pub async fn restart(&mut self) -> Result<(), irc::error::Error> {
-        trace!("Restarting IRC client...");
-        let mut backoff = backoff::ExponentialBackoff::default();
-        let mut retries = 0;
-        let mut success = false;
-        while !success {
-            retries += 1;
-            debug!("Restarting IRC... Try {retries}");
-            let result = self.restart_inner().await;
-            if result.is_ok() {
-                success = true;
-            } else if let Some(sleep_time) = backoff.next_backoff() {
-                let sleep_time_rounded = tokio::time::Duration::from_secs(sleep_time.as_secs());
-                debug!("Sleeping ~ {sleep_time_rounded:?} until next retry...");
-                tokio::time::sleep(sleep_time).await;
-            } else {
-                return result;
-            }
-        }
-        Ok(())
-    }
-
     pub async fn handle(&mut self, message: &Message) -> Result<(), IrcThreadError> {
         let author_nickname = message.source_nickname().unwrap_or("");
 
@@ -212,6 +271,7 @@ impl TwitchClient {
                 let channel_name = target.strip_prefix('#').unwrap_or(target).to_lowercase();
 
                 let msg_template = TwitchMessageSimple::new(
+                    events::get_tag(message, "id").unwrap_or("").to_string(),
                     channel_name.clone(),
                     author_nickname.to_string(),
                     msg.to_string()
@@ -219,6 +279,8 @@ impl TwitchClient {
                 // trace!("msg_template: {:?}", msg_template);
 
                 let mut messages_per_user = AHashMap::new();
+                let mut urgent_per_user: AHashMap<i64, Urgent> = AHashMap::new();
+                let mut trigger_ids_per_user: AHashMap<i64, Vec<i64>> = AHashMap::new();
 
                 macro_rules! append_trigger {
                     ($user:expr, $trig:expr) => {
@@ -230,8 +292,15 @@ impl TwitchClient {
                 }
 
 
-                let query = sqlx::query_as!(crate::db::TriggerRecordNoId,
-                        "SELECT discord_user_id, trigger, case_sensitive, regex FROM triggers WHERE discord_user_id IN (SELECT discord_user_id FROM channels WHERE channel = ?)",
+                let now = chrono::Utc::now().timestamp();
+                let query = sqlx::query_as!(crate::db::TriggerRecord,
+                        "SELECT id, discord_user_id, trigger, case_sensitive, regex, burst_count, burst_window, burst_distinct, urgent_minutes, escalate_to FROM triggers WHERE discord_user_id IN (SELECT discord_user_id FROM watched WHERE channel = ?)
+                            AND NOT disabled
+                            AND discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)
+                            AND NOT EXISTS (SELECT 1 FROM snoozes WHERE snoozes.discord_user_id = triggers.discord_user_id AND snoozes.until > ?
+                                AND (snoozes.channel = ? OR snoozes.trigger_id = triggers.id OR (snoozes.channel IS NULL AND snoozes.trigger_id IS NULL)))",
+                        channel_name,
+                        now,
                         channel_name);
 
                 let res = query.fetch_all(self.db_con.get_mut()).await;
@@ -267,30 +336,104 @@ impl TwitchClient {
                         continue;
                     }
 
-                    if regex {
-                        // TODO: Make sure regex in DB is valid (check when putting in)
-                        if case_sensitive {
-                            let re = regex::Regex::new(&trigger).unwrap();
-                            for mat in re.find_iter(msg) {
-                                append_trigger!(&discord_id, (mat.start() as u16, mat.end() as u16));
-                            }
-                        } else {
-                            let re = regex::Regex::new(format!("(?i:{})", trigger).as_str()).unwrap();
-                            for mat in re.find_iter(msg) {
-                                append_trigger!(&discord_id, (mat.start() as u16, mat.end() as u16));
-                            }
+                    let matches = find_matches(&trigger, case_sensitive, regex, msg);
+
+                    if row.burst_count > 0 {
+                        // Aggregate trigger, one summary instead of the single messages
+                        if matches.is_empty() {
+                            continue;
                         }
-                    } else if case_sensitive {
-                        for pos in msg.match_indices(&trigger) {
-                            append_trigger!(&discord_id, (pos.0 as u16, (pos.0 + trigger.len()) as u16));
+                        let rule = burst::BurstRule {
+                            trigger: trigger.clone(),
+                            regex,
+                            count: row.burst_count as usize,
+                            window: std::time::Duration::from_secs(row.burst_window as u64),
+                            distinct: row.burst_distinct,
+                        };
+                        if let Some(summary) = self.bursts.hit(&channel_name, discord_id, &rule, author_nickname, msg) {
+                            use colored::Colorize;
+                            info!("💥 #{} `{}` x{}", channel_name.green().to_string(), trigger, summary.count);
+                            self.outbox.push(TriggerEvent::Burst {
+                                receiver: discord_id as u64,
+                                summary,
+                                timestamp: chrono::Utc::now(),
+                            }).await.unwrap_or_else(|e| {
+                                error!("ERROR! Failed to queue event: {}", e);
+                            });
                         }
-                    } else {
-                        for pos in msg.to_lowercase().match_indices(&trigger.to_lowercase()) {
-                            append_trigger!(&discord_id, (pos.0 as u16, (pos.0 + trigger.len()) as u16));
+                        continue;
+                    }
+
+                    if !matches.is_empty() {
+                        trigger_ids_per_user.entry(discord_id).or_default().push(row.id);
+                        if row.urgent_minutes > 0 {
+                            // The most urgent trigger wins when several match
+                            let urgent = urgent_per_user.entry(discord_id).or_insert(Urgent {
+                                minutes: row.urgent_minutes,
+                                escalate_to: row.escalate_to,
+                            });
+                            if row.urgent_minutes < urgent.minutes {
+                                urgent.minutes = row.urgent_minutes;
+                            }
+                            if urgent.escalate_to.is_none() {
+                                urgent.escalate_to = row.escalate_to;
+                            }
                         }
                     }
+                    for trig in matches {
+                        append_trigger!(&discord_id, trig);
+                    }
+                }
+
+                // Forward the next messages of authors that triggered a notification
+                for (discord_id, trigger_ids) in self.followups.receivers(&channel_name, author_nickname) {
+                    if messages_per_user.contains_key(&discord_id) {
+                        continue;
+                    }
+                    self.send_follow_up(discord_id, &trigger_ids, &channel_name, author_nickname, msg).await?;
+                }
+                for (discord_id, msg) in messages_per_user.iter_mut() {
+                    let settings = crate::db::get_settings(self.db_con.get_mut(), *discord_id).await?;
+                    msg.urgent = urgent_per_user.remove(discord_id);
+                    msg.trigger_ids = trigger_ids_per_user.remove(discord_id).unwrap_or_default();
+                    if settings.context_lines > 0 {
+                        msg.context_before = self.history.last(&channel_name, settings.context_lines as usize);
+                    }
+                }
+
+                let line = ChatLine {
+                    timestamp: chrono::Utc::now(),
+                    author: author_nickname.to_string(),
+                    message: msg.to_string(),
+                };
+                self.pending_context.add_line(&channel_name, &line);
+
+                let mirrors = sqlx::query!("SELECT discord_channel_id FROM mirrors WHERE channel = ?", channel_name)
+                    .fetch_all(self.db_con.get_mut()).await?;
+                for row in mirrors {
+                    // Mirrors are best-effort, chat is dropped rather than blocking the triggers
+                    if let Err(e) = self.mirror_tx.try_send(MirrorLine {
+                        discord_channel_id: row.discord_channel_id as u64,
+                        line: line.clone(),
+                    }) {
+                        debug!("[IRC] Mirror queue full, dropped a line: {:?}", e);
+                    }
+                }
+
+                if let Some(chat_log_tx) = &self.chat_log_tx {
+                    if let Err(e) = chat_log_tx.try_send(chatlog::ChatLogLine {
+                        channel: channel_name.clone(),
+                        line: line.clone(),
+                    }) {
+                        debug!("[IRC] Chat log queue full, dropped a line: {:?}", e);
+                    }
                 }
 
+                self.history.push(&channel_name, line);
+
+                // Shared chat delivers the same message to every channel in the session
+                let source_id = events::get_tag(message, "source-id");
+
                 for (discord_id, msg) in messages_per_user {
                     use colored::Colorize;
                     // The message string is printed using the Debug trait just in case
@@ -298,24 +441,25 @@ impl TwitchClient {
                         msg.channel.green().to_string(),
                         msg.author.yellow().to_string(),
                         msg.message_highlighted_term());
-                    self.discord_tx.send(TriggerEvent::new(
-                        discord_id as u64,
-                        msg,
-                        chrono::Utc::now()
-                    )).await.unwrap_or_else(|e| {
-                        error!("ERROR! Too many events in queue, failed to add: {:?}", e);
-                    });
+                    match source_id {
+                        Some(source_id) => self.shared_chat.hold(discord_id, source_id, msg),
+                        None => self.deliver(discord_id, msg).await?,
+                    }
                 }
             }
-            Command::JOIN(ref _channels, ref _chan_keys,  ref _real_name) => {
+            Command::JOIN(ref _channels, ref _chan_keys,  ref _real_name) if author_nickname == self.nickname => {
                 // trace!("{} ({:?}) joined {}", author_nickname, real_name, channels);
-                if author_nickname == self.client.current_nickname() {
-                    // Just successfully joined a channel, report back?
-                }
+                // Just successfully joined a channel, report back?
+            }
+            // Membership (`twitch.tv/membership`) of other chatters
+            Command::JOIN(ref channels, _, _) => {
+                let channel_name = channels.strip_prefix('#').unwrap_or(channels).to_lowercase();
+                self.presence.record(&channel_name, author_nickname, true);
+            }
+            Command::PART(ref channels, _) if author_nickname != self.nickname => {
+                let channel_name = channels.strip_prefix('#').unwrap_or(channels).to_lowercase();
+                self.presence.record(&channel_name, author_nickname, false);
             }
-            // Command::PART(ref channels, ref comment) => {
-            //     trace!("{} left {} ({:?})", author_nickname, channels, comment);
-            // }
             // Command::QUIT(ref comment) => {
             //     trace!("{} quit ({:?})", author_nickname, comment);
             // }
@@ -327,6 +471,7 @@ impl TwitchClient {
                     Response::RPL_WELCOME => {
                         // info!("Connected to IRC");
                         info!("`{}` connected!", args[0]);
+                        self.nickname = args[0].clone();
                     }
                     Response::RPL_NAMREPLY => {
                         // trace!("{:?}", args);
@@ -338,14 +483,38 @@ impl TwitchClient {
             }
             Command::Raw(ref code, ref args) => {
                 trace!("raw: {} {:?}", code, args);
-                // TODO: Handle twitch-specific commands (ex: RECONNECT)
                 match code.as_str() {
                     "RECONNECT" => {
-                        info!("Got a `RECONNECT` on IRC, restarting IRC client...");
-                        self.restart().await?;
+                        // Handled by the supervisor
                     }
                     "USERNOTICE" => {
-
+                        if let Some(event) = events::parse_usernotice(message, args) {
+                            // Only seen when the bot is in the raided chat, EventSub covers the rest
+                            if let ChannelEvent::Raid { ref from, viewers } = event {
+                                let to = args[0].strip_prefix('#').unwrap_or(&args[0]).to_lowercase();
+                                self.follow_raid(eventsub::OutgoingRaid { from: from.to_lowercase(), to, viewers }).await?;
+                            }
+                            self.notify_channel_event(&args[0], event).await?;
+                        }
+                    }
+                    "CLEARCHAT" => {
+                        if let Some(event) = events::parse_clearchat(message, args) {
+                            if let ChannelEvent::Ban { ref username, .. } = event {
+                                self.retract_notifications(&args[0], None, Some(username)).await?;
+                            }
+                            self.notify_channel_event(&args[0], event).await?;
+                        }
+                    }
+                    "CLEARMSG" => {
+                        if let Some(message_id) = events::get_tag(message, "target-msg-id") {
+                            self.retract_notifications(&args[0], Some(message_id), None).await?;
+                        }
+                    }
+                    "ROOMSTATE" => {
+                        let channel_name = args[0].strip_prefix('#').unwrap_or(&args[0]).to_lowercase();
+                        for event in self.room_states.update(&channel_name, message) {
+                            self.notify_channel_event(&args[0], event).await?;
+                        }
                     }
                     _ => {}
                 };
@@ -357,4 +526,339 @@ impl TwitchClient {
         Ok(())
     }
 
+    /// Send `event` to everyone subscribed to its kind in `target` (the IRC channel)
+    async fn notify_channel_event(&mut self, target: &str, event: ChannelEvent) -> Result<(), IrcThreadError> {
+        let channel_name = target.strip_prefix('#').unwrap_or(target).to_lowercase();
+        let kind = event.kind().as_str();
+        let event_target = event.target();
+        let now = chrono::Utc::now().timestamp();
+
+        let receivers = sqlx::query!(
+            "SELECT DISTINCT events.discord_user_id FROM events INNER JOIN watched ON watched.discord_user_id = events.discord_user_id WHERE watched.channel = ? AND events.kind = ? AND events.target = ?
+                AND events.discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)
+                AND NOT EXISTS (SELECT 1 FROM snoozes WHERE snoozes.discord_user_id = events.discord_user_id AND snoozes.until > ?
+                    AND (snoozes.channel = ? OR (snoozes.channel IS NULL AND snoozes.trigger_id IS NULL)))",
+            channel_name,
+            kind,
+            event_target,
+            now,
+            channel_name)
+            .fetch_all(self.db_con.get_mut()).await?;
+
+        if !receivers.is_empty() {
+            use colored::Colorize;
+            info!("📣 #{} {:?}", channel_name.green().to_string(), event);
+        }
+        for row in receivers {
+            self.outbox.push(TriggerEvent::Channel {
+                receiver: row.discord_user_id as u64,
+                channel: channel_name.clone(),
+                event: event.clone(),
+                timestamp: chrono::Utc::now(),
+            }).await.unwrap_or_else(|e| {
+                error!("ERROR! Failed to queue event: {}", e);
+            });
+        }
+        Ok(())
+    }
+
+    /// Ask Discord to edit or remove the DMs made from deleted messages,
+    /// either one message (`message_id`) or everything by `author` (purge, timeout or ban)
+    async fn retract_notifications(&mut self, target: &str, message_id: Option<&str>, author: Option<&str>) -> Result<(), IrcThreadError> {
+        let channel_name = target.strip_prefix('#').unwrap_or(target).to_lowercase();
+
+        // DMs still waiting in the outbox are dropped before anyone sees them
+        let res = sqlx::query!(
+            r#"DELETE FROM outbox WHERE status = 'pending' AND (
+                (json_extract(payload, '$.Message.message.channel') = ?
+                    AND (json_extract(payload, '$.Message.message.id') = ? OR json_extract(payload, '$.Message.message.author') = ?))
+                OR (json_extract(payload, '$.FollowUp.channel') = ? AND json_extract(payload, '$.FollowUp.author') = ?))"#,
+            channel_name,
+            message_id,
+            author,
+            channel_name,
+            author)
+            .execute(self.db_con.get_mut()).await?;
+        if res.rows_affected() > 0 {
+            trace!("Dropped {} queued notifications of deleted messages", res.rows_affected());
+        }
+        // Same for digests, deleted messages don't show up in them
+        sqlx::query!("DELETE FROM digest_items WHERE channel = ? AND (twitch_message_id = ? OR author = ?)",
+            channel_name,
+            message_id,
+            author)
+            .execute(self.db_con.get_mut()).await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT notifications.id, notifications.discord_user_id, dm_channel_id, dm_message_id, author, line,
+                COALESCE(settings.on_delete, 'edit') AS "on_delete!: OnDelete"
+                FROM notifications LEFT JOIN settings ON settings.discord_user_id = notifications.discord_user_id
+                WHERE channel = ? AND (twitch_message_id = ? OR author = ?)"#,
+            channel_name,
+            message_id,
+            author)
+            .fetch_all(self.db_con.get_mut()).await?;
+
+        for row in rows {
+            if row.on_delete == OnDelete::Keep {
+                continue;
+            }
+            trace!("Retracting notification {} ({:?})", row.id, row.on_delete);
+            self.outbox.push(TriggerEvent::Retract {
+                receiver: row.discord_user_id as u64,
+                dm_channel_id: row.dm_channel_id as u64,
+                dm_message_id: row.dm_message_id as u64,
+                channel: channel_name.clone(),
+                author: row.author,
+                remove: row.on_delete == OnDelete::Remove,
+                line: row.line,
+            }).await.unwrap_or_else(|e| {
+                error!("ERROR! Failed to queue event: {}", e);
+            });
+            sqlx::query!("DELETE FROM notifications WHERE id = ?", row.id)
+                .execute(self.db_con.get_mut()).await?;
+        }
+        Ok(())
+    }
+
+    /// Temporarily watch the raided channel for users who monitor the raider and opted in
+    ///
+    /// Raids come from the raided chat (USERNOTICE, when the bot is there) and from EventSub,
+    /// a raid reported by both is only followed once.
+    pub async fn follow_raid(&mut self, raid: eventsub::OutgoingRaid) -> Result<(), IrcThreadError> {
+        let eventsub::OutgoingRaid { from, to: channel_name, viewers } = raid;
+        let event = ChannelEvent::Raid { from: from.clone(), viewers };
+        let now = chrono::Utc::now();
+        let now_ts = now.timestamp();
+
+        let followers = sqlx::query!(
+            "SELECT DISTINCT channels.discord_user_id, settings.raid_follow_minutes FROM channels INNER JOIN settings ON settings.discord_user_id = channels.discord_user_id WHERE channels.channel = ? AND settings.raid_follow_minutes > 0 AND channels.discord_user_id NOT IN (SELECT discord_user_id FROM channels WHERE channel = ?)
+                AND channels.discord_user_id NOT IN (SELECT discord_user_id FROM follows WHERE channel = ? AND raided_from = ? AND expires_at > ?)
+                AND channels.discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)",
+            from,
+            channel_name,
+            channel_name,
+            from,
+            now_ts)
+            .fetch_all(self.db_con.get_mut()).await?;
+        if followers.is_empty() {
+            return Ok(());
+        }
+
+        let was_watched = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", channel_name)
+            .fetch_one(self.db_con.get_mut()).await?
+            .result == 1;
+
+        for row in followers {
+            let expires_at = now.timestamp() + row.raid_follow_minutes * 60;
+            sqlx::query!("INSERT INTO follows (discord_user_id, channel, raided_from, expires_at) VALUES (?, ?, ?, ?)",
+                row.discord_user_id,
+                channel_name,
+                from,
+                expires_at)
+                .execute(self.db_con.get_mut()).await?;
+
+            self.outbox.push(TriggerEvent::Channel {
+                receiver: row.discord_user_id as u64,
+                channel: channel_name.clone(),
+                event: event.clone(),
+                timestamp: now,
+            }).await.unwrap_or_else(|e| {
+                error!("ERROR! Failed to queue event: {}", e);
+            });
+        }
+        info!("Following raid #{} -> #{}", from, channel_name);
+
+        if !was_watched {
+            if let Err(e) = self.irc.join(format!("#{}", channel_name)) {
+                error!("[IRC] Error queueing message: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget in-memory follow-up, flood control and burst state that ran out
+    pub fn prune(&mut self) {
+        self.followups.prune();
+        self.flood.prune();
+        self.bursts.prune();
+    }
+
+    /// Drop expired raid follows and leave channels nobody watches anymore
+    pub async fn expire_follows(&mut self) -> Result<(), IrcThreadError> {
+        let now = chrono::Utc::now().timestamp();
+        let expired = sqlx::query!("SELECT DISTINCT channel FROM follows WHERE expires_at <= ?", now)
+            .fetch_all(self.db_con.get_mut()).await?;
+        if expired.is_empty() {
+            return Ok(());
+        }
+        sqlx::query!("DELETE FROM follows WHERE expires_at <= ?", now)
+            .execute(self.db_con.get_mut()).await?;
+
+        for row in expired {
+            let watched = sqlx::query!("SELECT EXISTS(SELECT 1 FROM joined_channels WHERE channel = ?) AS result", row.channel)
+                .fetch_one(self.db_con.get_mut()).await?
+                .result == 1;
+            if !watched {
+                info!("Raid follow of #{} expired, leaving", row.channel);
+                if let Err(e) = self.irc.part(format!("#{}", row.channel)) {
+                    error!("[IRC] Error queueing message: {:?}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_trigger(&mut self, discord_id: i64, mut msg: TwitchMessageSimple) -> Result<(), IrcThreadError> {
+        let settings = crate::db::get_settings(self.db_con.get_mut(), discord_id).await?;
+        // Matches during quiet hours wait for the digest sent once they are over,
+        // urgent ones too (without an alert, nothing was pinged)
+        let quiet = quiet::quiet_now(self.db_con.get_mut(), discord_id).await?;
+        if quiet == Some(QuietAction::Drop) {
+            return Ok(());
+        }
+        let held = quiet.is_some();
+        if settings.delivery_mode != DeliveryMode::Instant || held {
+            digest::store(self.db_con.get_mut(), discord_id, &msg, held).await?;
+            // Urgent matches go out right away, the digest only repeats them
+            if held || (settings.delivery_mode == DeliveryMode::Digest && msg.urgent.is_none()) {
+                return Ok(());
+            }
+        }
+        // Only a DM that goes out now reports (and resets) what flood control held back
+        msg.suppressed = self.flood.take_suppressed(discord_id);
+        self.outbox.push(TriggerEvent::new(
+            discord_id as u64,
+            msg,
+            chrono::Utc::now()
+        )).await.unwrap_or_else(|e| {
+            error!("ERROR! Failed to queue event: {}", e);
+        });
+        Ok(())
+    }
+
+    /// Forward a follow-up line, only while a match would still be sent live:
+    /// one of the triggers behind it is enabled and not snoozed, and the user isn't digest-only or in quiet hours
+    async fn send_follow_up(&mut self, discord_id: i64, trigger_ids: &[i64], channel: &str, author: &str, message: &str) -> Result<(), IrcThreadError> {
+        let now = chrono::Utc::now().timestamp();
+        let trigger_ids = serde_json::to_string(trigger_ids).unwrap_or_default();
+        let live = sqlx::query!(
+            "SELECT EXISTS(SELECT 1 FROM triggers WHERE triggers.id IN (SELECT value FROM json_each(?)) AND NOT triggers.disabled
+                AND triggers.discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)
+                AND NOT EXISTS (SELECT 1 FROM snoozes WHERE snoozes.discord_user_id = triggers.discord_user_id AND snoozes.until > ?
+                    AND (snoozes.channel = ? OR snoozes.trigger_id = triggers.id OR (snoozes.channel IS NULL AND snoozes.trigger_id IS NULL)))) AS result",
+            trigger_ids,
+            now,
+            channel)
+            .fetch_one(self.db_con.get_mut()).await?
+            .result == 1;
+        if !live {
+            return Ok(());
+        }
+        let settings = crate::db::get_settings(self.db_con.get_mut(), discord_id).await?;
+        if settings.delivery_mode == DeliveryMode::Digest {
+            return Ok(());
+        }
+        // The match itself waits for the digest, its follow-ups only make sense live
+        if quiet::quiet_now(self.db_con.get_mut(), discord_id).await?.is_some() {
+            return Ok(());
+        }
+
+        self.outbox.push(TriggerEvent::FollowUp {
+            receiver: discord_id as u64,
+            channel: channel.to_string(),
+            author: author.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Utc::now(),
+        }).await.unwrap_or_else(|e| {
+            error!("ERROR! Failed to queue event: {}", e);
+        });
+        Ok(())
+    }
+
+    /// Send the batched membership changes to the users watching those chatters,
+    /// then refresh the list of watched chatters
+    pub async fn flush_presence(&mut self) -> Result<(), IrcThreadError> {
+        let now = chrono::Utc::now().timestamp();
+        let rows = sqlx::query!(
+            "SELECT presence.discord_user_id, presence.channel, presence.username FROM presence
+                WHERE presence.channel IN (SELECT channel FROM watched WHERE watched.discord_user_id = presence.discord_user_id)
+                AND presence.discord_user_id NOT IN (SELECT discord_user_id FROM dm_status WHERE undeliverable_since IS NOT NULL)
+                AND NOT EXISTS (SELECT 1 FROM snoozes WHERE snoozes.discord_user_id = presence.discord_user_id AND snoozes.until > ?
+                    AND snoozes.trigger_id IS NULL AND (snoozes.channel = presence.channel OR snoozes.channel IS NULL))",
+            now)
+            .fetch_all(self.db_con.get_mut()).await?;
+
+        let changes = self.presence.take_pending();
+        let mut changes_per_user: AHashMap<i64, Vec<PresenceChange>> = AHashMap::new();
+        for change in changes {
+            for row in rows.iter().filter(|row| row.channel == change.channel && row.username == change.username) {
+                changes_per_user.entry(row.discord_user_id).or_default().push(change.clone());
+            }
+        }
+        self.presence.set_watched(rows.into_iter()
+            .map(|row| (row.channel, row.username))
+            .collect());
+
+        for (discord_id, mut changes) in changes_per_user {
+            changes.sort_by(|a, b| (&a.channel, &a.username).cmp(&(&b.channel, &b.username)));
+            info!("👋 {} presence changes for {}", changes.len(), discord_id);
+            self.outbox.push(TriggerEvent::Presence {
+                receiver: discord_id as u64,
+                changes,
+                timestamp: chrono::Utc::now(),
+            }).await.unwrap_or_else(|e| {
+                error!("ERROR! Failed to queue event: {}", e);
+            });
+        }
+        Ok(())
+    }
+
+    /// Recent messages per channel, shared with the Discord commands
+    pub fn history(&self) -> ChatHistory {
+        self.history.clone()
+    }
+
+    /// Send the notification, or hold it a moment for the following lines if the receiver wants them
+    ///
+    /// Flood control runs here, once per shared chat message after its copies have been merged.
+    async fn deliver(&mut self, discord_id: i64, msg: TwitchMessageSimple) -> Result<(), IrcThreadError> {
+        let settings = crate::db::get_settings(self.db_con.get_mut(), discord_id).await?;
+        let rules = flood::FloodRules {
+            cooldown: std::time::Duration::from_secs(settings.cooldown_secs as u64),
+            trigger_cooldown: std::time::Duration::from_secs(settings.trigger_cooldown_secs as u64),
+            duplicate_window: std::time::Duration::from_secs(settings.duplicate_secs as u64),
+        };
+        // Urgent matches are never held back
+        if msg.urgent.is_none() && !self.flood.allow(discord_id, &rules, &msg.trigger_ids, &msg.channel, &msg.author, &msg.message) {
+            debug!("Suppressed a notification for {} (flood control)", discord_id);
+            return Ok(());
+        }
+        if settings.followup_secs > 0 {
+            let window = std::time::Duration::from_secs(settings.followup_secs as u64);
+            for channel in std::iter::once(&msg.channel).chain(msg.shared_with.iter()) {
+                self.followups.start(channel, &msg.author, discord_id, &msg.trigger_ids, window);
+            }
+        }
+        if settings.context_lines > 0 && settings.context_delay_secs > 0 {
+            self.pending_context.hold(discord_id, msg, settings.context_lines as usize,
+                                      std::time::Duration::from_secs(settings.context_delay_secs as u64));
+        } else {
+            self.send_trigger(discord_id, msg).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the held notifications, shared chat messages that are done collecting their channels
+    /// and messages that are done collecting the following lines
+    pub async fn flush_pending(&mut self) -> Result<(), IrcThreadError> {
+        for (discord_id, msg) in self.shared_chat.take_ready() {
+            self.deliver(discord_id, msg).await?;
+        }
+        for (discord_id, msg) in self.pending_context.take_ready() {
+            self.send_trigger(discord_id, msg).await?;
+        }
+        Ok(())
+    }
+
 }
@@ -0,0 +1,41 @@
+use ahash::{AHashMap, AHashSet};
+
+
+/// A watched chatter joined or left a channel
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PresenceChange {
+    pub channel: String,
+    pub username: String,
+    pub joined: bool,
+}
+
+/// Collects membership changes of watched chatters between flushes
+///
+/// Twitch sends `JOIN`/`PART` in delayed bursts (every ~10 seconds, and only while
+/// the chat is not too big), so changes are batched and only the last state is kept.
+#[derive(Debug, Default)]
+pub struct PresenceWatch {
+    /// `(channel, username)` someone is watching, refreshed on every flush
+    watched: AHashSet<(String, String)>,
+    /// Last seen state of a watched chatter since the last flush
+    pending: AHashMap<(String, String), bool>,
+}
+
+impl PresenceWatch {
+    pub fn record(&mut self, channel: &str, username: &str, joined: bool) {
+        let key = (channel.to_string(), username.to_string());
+        if self.watched.contains(&key) {
+            self.pending.insert(key, joined);
+        }
+    }
+
+    pub fn set_watched(&mut self, watched: AHashSet<(String, String)>) {
+        self.watched = watched;
+    }
+
+    pub fn take_pending(&mut self) -> Vec<PresenceChange> {
+        self.pending.drain()
+            .map(|((channel, username), joined)| PresenceChange { channel, username, joined })
+            .collect()
+    }
+}
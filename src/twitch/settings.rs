@@ -0,0 +1,123 @@
+use std::env;
+use std::fmt;
+use irc::client::prelude::Config;
+use thiserror::Error;
+
+
+const DEFAULT_SERVER: &str = "irc.chat.twitch.tv";
+const DEFAULT_PORT_TLS: u16 = 6697;
+const DEFAULT_PORT_PLAIN: u16 = 6667;
+
+#[derive(Debug, Error)]
+pub enum IrcSettingsError {
+    #[error("`{0}` is not a valid port")]
+    InvalidPort(String),
+    #[error("`{name}` must be a boolean, got `{value}`")]
+    InvalidBool { name: &'static str, value: String },
+    #[error("TWITCH_NICK and TWITCH_TOKEN must be set together")]
+    IncompleteLogin,
+}
+
+/// Credentials for logging in as a real Twitch account
+#[derive(Clone)]
+pub struct IrcLogin {
+    pub nickname: String,
+    /// Always stored with the `oauth:` prefix, never printed
+    token: String,
+}
+
+impl IrcLogin {
+    pub fn new(nickname: &str, token: &str) -> Self {
+        let token = if token.starts_with("oauth:") {
+            token.to_string()
+        } else {
+            format!("oauth:{}", token)
+        };
+        Self {
+            nickname: nickname.to_lowercase(),
+            token,
+        }
+    }
+}
+
+impl fmt::Debug for IrcLogin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IrcLogin")
+            .field("nickname", &self.nickname)
+            .field("token", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Where and how to connect to chat
+///
+/// Defaults to anonymous (`justinfan`) login on `irc.chat.twitch.tv:6697` over TLS.
+#[derive(Debug, Clone)]
+pub struct IrcSettings {
+    pub server: String,
+    pub port: u16,
+    pub use_tls: bool,
+    pub login: Option<IrcLogin>,
+}
+
+impl IrcSettings {
+    pub fn from_env() -> Result<Self, IrcSettingsError> {
+        let use_tls = match env::var("TWITCH_IRC_TLS") {
+            Ok(value) => parse_bool("TWITCH_IRC_TLS", &value)?,
+            Err(_) => true,
+        };
+        let port = match env::var("TWITCH_IRC_PORT") {
+            Ok(value) => value.parse::<u16>().map_err(|_| IrcSettingsError::InvalidPort(value))?,
+            Err(_) if use_tls => DEFAULT_PORT_TLS,
+            Err(_) => DEFAULT_PORT_PLAIN,
+        };
+        let login = match (env::var("TWITCH_NICK"), env::var("TWITCH_TOKEN")) {
+            (Ok(nickname), Ok(token)) => Some(IrcLogin::new(&nickname, &token)),
+            (Err(_), Err(_)) => None,
+            _ => return Err(IrcSettingsError::IncompleteLogin),
+        };
+
+        Ok(Self {
+            server: env::var("TWITCH_IRC_SERVER").unwrap_or_else(|_| DEFAULT_SERVER.to_string()),
+            port,
+            use_tls,
+            login,
+        })
+    }
+
+    /// Build the `irc` crate config, joining `channels` (with `#`) on connect
+    pub fn to_irc_config(&self, channels: Vec<String>) -> Config {
+        let (nickname, alt_nicks, password) = match &self.login {
+            Some(login) => (login.nickname.clone(), vec![], Some(login.token.clone())),
+            None => (
+                format!("justinfan{}", rand::random::<u32>()),
+                vec![ // Just in case the first one is taken
+                      format!("justinfan{}", rand::random::<u32>()),
+                      format!("justinfan{}", rand::random::<u32>()),
+                      format!("justinfan{}", rand::random::<u32>()),
+                ],
+                None,
+            ),
+        };
+
+        Config {
+            nickname: Some(nickname),
+            alt_nicks,
+            password,
+            // realname: Some("Offline_Frog".to_string()),
+            use_tls: Some(self.use_tls),
+            server: Some(self.server.clone()),
+            port: Some(self.port),
+            channels,
+            ..Config::default()
+        }
+    }
+}
+
+fn parse_bool(name: &'static str, value: &str) -> Result<bool, IrcSettingsError> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(IrcSettingsError::InvalidBool { name, value: value.to_string() }),
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+use ahash::AHashMap;
+use tokio::time::Instant;
+
+use super::TwitchMessageSimple;
+
+
+/// How long to wait for the other copies of a shared chat message
+pub const SHARED_CHAT_WINDOW: Duration = Duration::from_secs(2);
+
+/// Collects the copies of a shared chat message (same `source-id` tag, several channels),
+/// so every receiver gets one notification listing all channels it matched in
+#[derive(Debug, Default)]
+pub struct SharedChat {
+    pending: AHashMap<(i64, String), (Instant, TwitchMessageSimple)>,
+}
+
+impl SharedChat {
+    /// Hold `message` (already matched for `receiver` in its channel) until the window is over
+    pub fn hold(&mut self, receiver: i64, source_id: &str, message: TwitchMessageSimple) {
+        match self.pending.get_mut(&(receiver, source_id.to_string())) {
+            Some((_, held)) => {
+                if held.channel != message.channel && !held.shared_with.contains(&message.channel) {
+                    held.shared_with.push(message.channel);
+                }
+                for trig in message.triggers {
+                    held.add_trigger(trig);
+                }
+                for trigger_id in message.trigger_ids {
+                    if !held.trigger_ids.contains(&trigger_id) {
+                        held.trigger_ids.push(trigger_id);
+                    }
+                }
+                if held.urgent.is_none() {
+                    held.urgent = message.urgent;
+                }
+            }
+            None => {
+                self.pending.insert((receiver, source_id.to_string()), (Instant::now(), message));
+            }
+        }
+    }
+
+    /// Messages whose window is over
+    pub fn take_ready(&mut self) -> Vec<(i64, TwitchMessageSimple)> {
+        let ready = self.pending.iter()
+            .filter(|(_, (since, _))| since.elapsed() >= SHARED_CHAT_WINDOW)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        ready.into_iter()
+            .filter_map(|key| self.pending.remove(&key).map(|(_, message)| (key.0, message)))
+            .collect()
+    }
+}
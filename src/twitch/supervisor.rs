@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use ahash::AHashSet;
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use futures_util::StreamExt;
+use irc::client::prelude::*;
+use thiserror::Error;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, info, error, warn};
+
+use super::{IrcMessageEvent, IrcSettings};
+
+
+/// How often the watchdog looks at the connection
+const WATCHDOG_TICK: Duration = Duration::from_secs(15);
+/// Silence on the wire after which we send our own `PING`
+const PING_AFTER: Duration = Duration::from_secs(4 * 60);
+/// Silence (after the `PING`) after which the connection is considered stalled
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+/// Twitch allows 20 JOINs per 10 seconds
+const JOIN_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connecting and logging in, nothing received yet
+    Connecting,
+    /// Logged in (`RPL_WELCOME`), chat is flowing
+    Connected,
+    /// Connection lost, waiting for the next attempt
+    Degraded,
+}
+
+/// Why a session ended without an IRC error
+#[derive(Debug)]
+enum SessionEnd {
+    /// Twitch asked us to reconnect (`RECONNECT`)
+    Reconnect,
+    /// Nothing received for too long, even after a `PING`
+    Stalled,
+    /// The server closed the stream
+    Closed,
+}
+
+/// Owns the IRC connection: connects, watches it and reconnects forever
+///
+/// Incoming messages are forwarded as [`IrcMessageEvent::Incoming`],
+/// outgoing commands are queued through a [`SupervisorHandle`] and sent once connected.
+/// JOINs (including the initial ones) are paced to stay within Twitch rate limits.
+pub struct Supervisor {
+    settings: IrcSettings,
+    /// Channels (with `#`) to join on the next connect, follows every JOIN/PART we send
+    channels: AHashSet<String>,
+    /// Channels waiting for their JOIN to be sent
+    pending_joins: VecDeque<String>,
+    incoming: mpsc::Sender<IrcMessageEvent>,
+    outgoing_tx: mpsc::UnboundedSender<Command>,
+    outgoing_rx: mpsc::UnboundedReceiver<Command>,
+    state: watch::Sender<ConnectionState>,
+    /// Channels the server confirmed we are in
+    joined: watch::Sender<AHashSet<String>>,
+}
+
+#[derive(Debug, Error)]
+#[error("IRC supervisor is not running")]
+pub struct SupervisorGone;
+
+#[derive(Debug, Clone)]
+pub struct SupervisorHandle {
+    outgoing: mpsc::UnboundedSender<Command>,
+    state: watch::Receiver<ConnectionState>,
+    joined: watch::Receiver<AHashSet<String>>,
+}
+
+impl SupervisorHandle {
+    /// Queue a command, it is sent as soon as the connection is up
+    pub fn send(&self, command: Command) -> Result<(), SupervisorGone> {
+        self.outgoing.send(command).map_err(|_| SupervisorGone)
+    }
+
+    /// Queue a JOIN of `channel` (with `#`), sent through the join pacer
+    pub fn join(&self, channel: String) -> Result<(), SupervisorGone> {
+        self.send(Command::JOIN(channel, None, None))
+    }
+
+    /// Queue a PART of `channel` (with `#`)
+    pub fn part(&self, channel: String) -> Result<(), SupervisorGone> {
+        self.send(Command::PART(channel, None))
+    }
+
+    /// Subscribe to connection state changes
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Channels (with `#`) we are actually in right now
+    pub fn joined(&self) -> AHashSet<String> {
+        self.joined.borrow().clone()
+    }
+}
+
+impl Supervisor {
+    pub fn new(settings: IrcSettings, channels: Vec<String>, incoming: mpsc::Sender<IrcMessageEvent>) -> Self {
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (state, _) = watch::channel(ConnectionState::Connecting);
+        let (joined, _) = watch::channel(AHashSet::new());
+        Self {
+            settings,
+            channels: channels.into_iter().collect(),
+            pending_joins: VecDeque::new(),
+            incoming,
+            outgoing_tx,
+            outgoing_rx,
+            state,
+            joined,
+        }
+    }
+
+    pub fn handle(&self) -> SupervisorHandle {
+        SupervisorHandle {
+            outgoing: self.outgoing_tx.clone(),
+            state: self.state.subscribe(),
+            joined: self.joined.subscribe(),
+        }
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        debug!("[IRC] Connection state: {:?}", state);
+        self.state.send_replace(state);
+    }
+
+    /// Never returns, every failure ends up in a (jittered) retry
+    pub async fn run(mut self) {
+        let mut backoff = ExponentialBackoff {
+            max_interval: Duration::from_secs(5 * 60),
+            max_elapsed_time: None,
+            ..ExponentialBackoff::default()
+        };
+        let mut retries = 0;
+        loop {
+            self.set_state(ConnectionState::Connecting);
+            match self.session(&mut backoff).await {
+                Ok(SessionEnd::Reconnect) => {
+                    info!("[IRC] Got a `RECONNECT`, reconnecting...");
+                }
+                Ok(end) => {
+                    warn!("[IRC] Connection ended ({:?}), reconnecting...", end);
+                }
+                Err(e) => {
+                    error!("[IRC] Connection error, reconnecting: {:?}", e);
+                }
+            }
+            self.set_state(ConnectionState::Degraded);
+            self.joined.send_replace(AHashSet::new());
+
+            retries += 1;
+            // `max_elapsed_time: None` means the backoff never runs out
+            let sleep_time = backoff.next_backoff().unwrap_or(backoff.max_interval);
+            debug!("[IRC] Try {retries}, sleeping ~ {:?} until next retry...", Duration::from_secs(sleep_time.as_secs()));
+            tokio::time::sleep(sleep_time).await;
+        }
+    }
+
+    async fn session(&mut self, backoff: &mut ExponentialBackoff) -> Result<SessionEnd, irc::error::Error> {
+        // Channels are joined after `RPL_WELCOME`, through the rate limit
+        let config = self.settings.to_irc_config(vec![]);
+        let mut client = Client::from_config(config).await?;
+        client.identify()?;
+        // Tags, Twitch-specific commands (USERNOTICE, CLEARCHAT, ROOMSTATE, RECONNECT, ...)
+        // and JOIN/PART of other chatters
+        client.send(Command::CAP(
+            None,
+            irc::proto::CapSubCommand::REQ,
+            None,
+            Some("twitch.tv/tags twitch.tv/commands twitch.tv/membership".to_string())))?;
+        let mut stream = client.stream()?;
+
+        let mut connected = false;
+        let mut last_seen = Instant::now();
+        let mut ping_sent = false;
+        let mut watchdog = tokio::time::interval(WATCHDOG_TICK);
+        let mut join_pacer = tokio::time::interval(JOIN_INTERVAL);
+        join_pacer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                message = stream.next() => {
+                    let message = match message {
+                        Some(message) => message?,
+                        None => return Ok(SessionEnd::Closed),
+                    };
+                    last_seen = Instant::now();
+                    ping_sent = false;
+
+                    match message.command {
+                        Command::Response(Response::RPL_WELCOME, _) => {
+                            connected = true;
+                            backoff.reset();
+                            self.pending_joins = self.channels.iter().cloned().collect();
+                            self.set_state(ConnectionState::Connected);
+                        }
+                        Command::Response(Response::RPL_ENDOFNAMES, _) => {
+                            self.publish_joined(&client);
+                        }
+                        // Membership also sends the PARTs of other chatters
+                        Command::PART(..) if message.source_nickname() == Some(client.current_nickname()) => {
+                            self.publish_joined(&client);
+                        }
+                        Command::Raw(ref code, _) if code == "RECONNECT" => {
+                            return Ok(SessionEnd::Reconnect);
+                        }
+                        _ => {}
+                    }
+
+                    if let Err(e) = self.incoming.send(IrcMessageEvent::Incoming(message)).await {
+                        error!("[IRC] Error sending message to irc thread: {:?}", e);
+                    }
+                }
+                Some(command) = self.outgoing_rx.recv(), if connected => {
+                    self.track_channels(&command);
+                    match command {
+                        // Sent by `join_pacer`
+                        Command::JOIN(..) => {}
+                        Command::PART(..) => {
+                            client.send(command)?;
+                            self.publish_joined(&client);
+                        }
+                        command => client.send(command)?,
+                    }
+                }
+                _ = join_pacer.tick(), if connected && !self.pending_joins.is_empty() => {
+                    if let Some(channel) = self.pending_joins.pop_front() {
+                        client.send_join(channel)?;
+                    }
+                }
+                _ = watchdog.tick() => {
+                    let silence = last_seen.elapsed();
+                    if silence > PING_AFTER + PING_TIMEOUT {
+                        return Ok(SessionEnd::Stalled);
+                    }
+                    if silence > PING_AFTER && !ping_sent {
+                        debug!("[IRC] Nothing received for {:?}, sending PING", Duration::from_secs(silence.as_secs()));
+                        client.send(Command::PING(self.settings.server.clone(), None))?;
+                        ping_sent = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn publish_joined(&self, client: &Client) {
+        let joined = client.list_channels().unwrap_or_default();
+        self.joined.send_replace(joined.into_iter().collect());
+    }
+
+    /// Remember JOINs and PARTs so a reconnect rejoins the right channels,
+    /// even the ones not confirmed by the server yet
+    fn track_channels(&mut self, command: &Command) {
+        match command {
+            Command::JOIN(channels, _, _) => {
+                for channel in channels.split(',') {
+                    self.channels.insert(channel.to_string());
+                    if !self.pending_joins.iter().any(|c| c == channel) {
+                        self.pending_joins.push_back(channel.to_string());
+                    }
+                }
+            }
+            Command::PART(channels, _) => {
+                for channel in channels.split(',') {
+                    self.channels.remove(channel);
+                    self.pending_joins.retain(|c| c != channel);
+                }
+            }
+            _ => {}
+        }
+    }
+}